@@ -0,0 +1,9 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use fdedup::cache::{self,PathMap};
+
+// any bytes given as a cache file: every format version is tried
+fuzz_target!(|data: &[u8]| {
+    let _ = cache::decode(data, &mut PathMap::default());
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use fdedup::cache::{self,PathMap};
+
+// the records of the current format, behind a valid header
+fuzz_target!(|data: &[u8]| {
+    let mut bytes = cache::MAGIC.to_vec();
+    bytes.extend_from_slice(&cache::VERSION.to_le_bytes());
+    bytes.extend_from_slice(data);
+    let _ = cache::decode(&bytes, &mut PathMap::default());
+});
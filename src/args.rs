@@ -1,3 +1,6 @@
+pub mod command;
+pub use self::command::{Command,CacheCommand};
+
 #[cfg(feature = "clap")]
 pub mod clap;
 #[cfg(feature = "clap")]
@@ -1,24 +1,89 @@
-use crate::{types::PathData, DEFAULT_CACHE_FILE};
+use crate::{types::PathData, output::Format};
+use crate::sort::{SortOrder,GroupKey,PathKey};
+use crate::report::ReportOptions;
+use crate::cache::{CacheCheck,Compression};
+use super::{command,Command};
 use std::env;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct Args {
     pub folders: Vec<PathData>,
+    pub reference: Vec<PathData>,
     pub disable_cache: bool,
     pub empty_cache: bool,
-    pub cache_file: PathData,
+    pub cache_file: Option<PathData>,
+    pub cache_in_root: bool,
+    pub cache_check: CacheCheck,
+    pub cache_compression: Option<Compression>,
+    pub prune_unseen: bool,
+    pub prune_missing: bool,
+    pub max_age: Option<String>,
+    pub cache_root: Option<PathData>,
+    pub remap: Vec<String>,
+    pub xattr: bool,
     pub normalize: bool,
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    pub settle: Option<u64>,
+    pub sort: SortOrder<GroupKey>,
+    pub sort_paths: SortOrder<PathKey>,
+    pub format: Option<Format>,
+    pub top_pairs: usize,
+    pub print0: bool,
+    pub show_size: bool,
+    pub same_line: bool,
+    pub omit_first: bool,
+    pub summarize: bool,
+    pub events: bool,
+    pub stats: bool,
     pub threads: Option<usize>,
     pub verbosity: u8,
+    pub command: Option<Command>,
 }
 
 impl Args {
     pub fn new() -> Self {
+        let args : Vec<String> = env::args().skip(1).collect();
+        let (command, command_cache_file) = match command::parse(&args) {
+            Ok(Some((command, cache_file))) => (Some(command), cache_file),
+            Ok(None) => (None, None),
+            Err(e) => {
+                println!("{}",e);
+                std::process::exit(1)
+            }
+        };
+        // a command has its own options, the scan options keep their defaults
+        let args = if command.is_some() { vec![] } else { args };
         let mut verbosity : u8 = 0;
         let mut empty_cache = false;
         let mut disable_cache = false;
         let mut normalize = false;
-        let mut folders : Vec<PathData> = env::args().skip(1).filter_map(|arg| {
+        let mut reference = vec![];
+        let mut newer_than = None;
+        let mut older_than = None;
+        let mut settle = None;
+        let mut sort = SortOrder::default();
+        let mut sort_paths = SortOrder::default();
+        let mut format = None;
+        let mut top_pairs = ReportOptions::default().top_pairs;
+        let mut cache_check = CacheCheck::default();
+        let mut cache_compression = None;
+        let mut prune_unseen = false;
+        let mut prune_missing = false;
+        let mut max_age = None;
+        let mut cache_root = None;
+        let mut remap = vec![];
+        let mut xattr = false;
+        let mut cache_in_root = false;
+        let mut print0 = false;
+        let mut show_size = false;
+        let mut same_line = false;
+        let mut omit_first = false;
+        let mut summarize = false;
+        let mut events = false;
+        let mut stats = false;
+        let mut folders : Vec<PathData> = args.into_iter().filter_map(|arg| {
             match arg.as_str() {
                 "-v" | "--verbose" => { verbosity += 1; None }
                 "-vv" => { verbosity += 2; None }
@@ -28,20 +93,91 @@ impl Args {
                 "-e" | "--empty-cache" =>  { empty_cache = true; None }
                 "-d" | "--disable-cache" =>  { disable_cache = true; None }
                 "-n" | "--normalize" =>  { normalize = true; None }
+                "--events" =>  { events = true; None }
+                "--stats" =>  { stats = true; None }
+                "--prune-unseen" =>  { prune_unseen = true; None }
+                "--prune-missing" =>  { prune_missing = true; None }
+                "--xattr" =>  { xattr = true; None }
+                "--cache-in-root" =>  { cache_in_root = true; None }
+                "-0" | "--print0" =>  { print0 = true; None }
+                "-S" | "--size" =>  { show_size = true; None }
+                "-1" | "--sameline" =>  { same_line = true; None }
+                "-f" | "--omitfirst" | "--omit-first" =>  { omit_first = true; None }
+                "-m" | "--summarize" =>  { summarize = true; None }
+                "-r" | "--recurse" | "-q" | "--quiet" | "--noempty" =>  { None }
+                s if s.starts_with("--newer-than=") => { newer_than = Some(s["--newer-than=".len()..].to_string()); None }
+                s if s.starts_with("--older-than=") => { older_than = Some(s["--older-than=".len()..].to_string()); None }
+                s if s.starts_with("--settle=") => { settle = Some(value(s, "--settle=")); None }
+                s if s.starts_with("--sort=") => { sort = value(s, "--sort="); None }
+                s if s.starts_with("--sort-paths=") => { sort_paths = value(s, "--sort-paths="); None }
+                s if s.starts_with("--cache-check=") => { cache_check = value(s, "--cache-check="); None }
+                s if s.starts_with("--cache-compression=") => { cache_compression = Some(value(s, "--cache-compression=")); None }
+                s if s.starts_with("--max-age=") => { max_age = Some(s["--max-age=".len()..].to_string()); None }
+                s if s.starts_with("--cache-root=") => { cache_root = Some(PathData::from(&s["--cache-root=".len()..])); None }
+                s if s.starts_with("--remap=") => { remap.push(s["--remap=".len()..].to_string()); None }
+                s if s.starts_with("--format=") => { format = Some(value(s, "--format=")); None }
+                s if s.starts_with("--top-pairs=") => { top_pairs = value(s, "--top-pairs="); None }
+                s if s.starts_with("--reference=") => { reference.push(PathData::from(&s["--reference=".len()..])); None }
                 s => Some(PathData::from(s)),
             }
         }).collect();
+        if print0 && format.is_some() {
+            println!("-0/--print0 can't be used with --format");
+            std::process::exit(1)
+        }
+        if events && format != Some(Format::Ndjson) {
+            println!("--events needs --format ndjson");
+            std::process::exit(1)
+        }
         if folders.len() < 1 {
             folders = vec![PathData::from(".")];
         }
         Self {
             folders,
+            reference,
             disable_cache,
             empty_cache,
-            cache_file : PathData::from(DEFAULT_CACHE_FILE),
+            cache_file : command_cache_file,
+            cache_in_root,
+            cache_check,
+            cache_compression,
+            prune_unseen,
+            prune_missing,
+            max_age,
+            cache_root,
+            remap,
+            xattr,
             normalize,
+            newer_than,
+            older_than,
+            settle,
+            sort,
+            sort_paths,
+            format,
+            top_pairs,
+            print0,
+            show_size,
+            same_line,
+            omit_first,
+            summarize,
+            events,
+            stats,
             threads : Some(1),
             verbosity,
+            command,
+        }
+    }
+}
+
+/// The value of an --option=value argument, exits with the reason when it
+/// is invalid (as the clap and getopts parsers do)
+fn value<T>(arg : &str, prefix : &str) -> T where T : FromStr, T::Err : std::fmt::Display {
+    let value = &arg[prefix.len()..];
+    match value.parse() {
+        Ok(v) => v,
+        Err(e) => {
+            println!("invalid value '{}' for '{}': {}", value, prefix.trim_end_matches('='), e);
+            std::process::exit(1)
         }
     }
 }
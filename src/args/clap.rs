@@ -1,11 +1,19 @@
-use crate::{types::PathData, DEFAULT_CACHE_FILE};
-use clap::Parser;
+use crate::{types::PathData, output::Format};
+use crate::sort::{SortOrder,GroupKey,PathKey};
+use crate::cache::{CacheCheck,Compression};
+use super::Command;
+use clap::{CommandFactory,Parser};
 
 #[cfg(not(feature = "threads"))]
 const HIDE_THREADS : bool = true;
 #[cfg(feature = "threads")]
 const HIDE_THREADS : bool = false;
 
+#[cfg(not(feature = "xattr"))]
+const HIDE_XATTR : bool = true;
+#[cfg(feature = "xattr")]
+const HIDE_XATTR : bool = false;
+
 #[cfg(not(feature = "verbose"))]
 const HIDE_VERBOSE : bool = true;
 #[cfg(feature = "verbose")]
@@ -18,22 +26,130 @@ pub struct Args {
     #[arg(default_value = ".")]
     pub folders: Vec<PathData>,
 
+    /// Read-only reference folder: matched against but never acted on (repeatable)
+    #[arg(long, value_name = "<DIR>")]
+    pub reference: Vec<PathData>,
+
     /// Turn OFF caching of file hashes
-    #[arg(short, long, default_value_t = false)]
+    #[arg(short, long, default_value_t = false, global = true)]
     pub disable_cache: bool,
     
     /// Start with empty cache
     #[arg(short, long, default_value_t = false, conflicts_with="disable_cache")]
     pub empty_cache: bool,
     
-    /// Where to store the cache
-    #[arg(short, long, value_name = "<FILE>", default_value = DEFAULT_CACHE_FILE, conflicts_with="disable_cache")]
-    pub cache_file: PathData,
+    /// Where to store the cache [default: .fdedup_cache.bin in the first folder if it exists, else a per-user cache in $XDG_CACHE_HOME/fdedup]
+    #[arg(short, long, value_name = "<FILE>", conflicts_with="disable_cache", global = true)]
+    pub cache_file: Option<PathData>,
+
+    /// Keep the cache in the first folder (.fdedup_cache.bin) instead of the per-user cache
+    #[arg(long, default_value_t = false, conflicts_with_all=["disable_cache", "cache_file"])]
+    pub cache_in_root: bool,
+
+    /// How cached digests are validated: fast (mtime, size), strict (+ inode, device, ctime) or paranoid (+ rehash)
+    #[arg(long, value_enum, value_name = "<CHECK>", default_value = "strict")]
+    pub cache_check: CacheCheck,
+
+    /// Compression of the cache file: none or zstd (the paths are always prefix compressed) [default: keep the current one]
+    #[arg(long, value_enum, value_name = "<TYPE>")]
+    pub cache_compression: Option<Compression>,
+
+    /// Drop the cache entries of files under the scanned folders that were not found
+    #[arg(long, default_value_t = false)]
+    pub prune_unseen: bool,
+
+    /// Drop the cache entries of files that no longer exist
+    #[arg(long, default_value_t = false)]
+    pub prune_missing: bool,
+
+    /// Drop the cache entries hashed longer ago than this (30days, 12h...)
+    #[arg(long, value_name = "<AGE>")]
+    pub max_age: Option<String>,
+
+    /// Store the cached paths relative to this folder, so the cache survives moves and remounts
+    #[arg(long, value_name = "<DIR>")]
+    pub cache_root: Option<PathData>,
+
+    /// Read the cached paths starting with OLD as starting with NEW (repeatable)
+    #[arg(long, value_name = "<OLD=NEW>")]
+    pub remap: Vec<String>,
+
+    /// Also keep the digests in user.fdedup.* extended attributes of the files (reused with --cache-check fast only)
+    #[arg(long, default_value_t = false, hide=HIDE_XATTR)]
+    pub xattr: bool,
 
     /// Normalize pathnames to Linux-style /
     #[arg(short, long, default_value_t = false)]
     pub normalize: bool,
 
+    /// Only scan files modified after this date or duration ago (2023-03-01, 3days, 12h...)
+    #[arg(long, value_name = "<WHEN>")]
+    pub newer_than: Option<String>,
+
+    /// Only scan files modified before this date or duration ago (2023-03-01, 3days, 12h...)
+    #[arg(long, value_name = "<WHEN>")]
+    pub older_than: Option<String>,
+
+    /// Skip files modified within the last SECS seconds (probably still being written)
+    #[arg(long, value_name = "<SECS>")]
+    pub settle: Option<u64>,
+
+    /// Order of the groups: size, wasted, count, path or mtime, optionally followed by :asc or :desc
+    #[arg(long, value_name = "<KEY[:ORDER]>", default_value = "size")]
+    pub sort: SortOrder<GroupKey>,
+
+    /// Order of the paths in a group: name, mtime or depth, optionally followed by :asc or :desc
+    #[arg(long, value_name = "<KEY[:ORDER]>", default_value = "name")]
+    pub sort_paths: SortOrder<PathKey>,
+
+    /// Output format [default: text, or fdupes with -S, -1, -f, -m or when run as fdupes/jdupes]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Number of directory pairs sharing the most content shown by the dirs and html formats
+    #[arg(long, value_name = "<N>", default_value_t = 20)]
+    pub top_pairs: usize,
+
+    /// Separate paths with NUL and groups with a double NUL (with -f: only the non-survivors, for xargs -0 rm)
+    #[arg(short='0', long="print0", default_value_t = false, conflicts_with="format")]
+    pub print0: bool,
+
+    /// fdupes: show the size of the duplicate files
+    #[arg(short='S', long="size", default_value_t = false)]
+    pub show_size: bool,
+
+    /// fdupes: list each group of duplicates on a single line
+    #[arg(short='1', long="sameline", default_value_t = false)]
+    pub same_line: bool,
+
+    /// fdupes: omit the survivor (and the reference files) of each group (also applies to -0)
+    #[arg(short='f', long="omitfirst", visible_alias="omit-first", default_value_t = false)]
+    pub omit_first: bool,
+
+    /// fdupes: only print a summary of the duplicates
+    #[arg(short='m', long="summarize", default_value_t = false)]
+    pub summarize: bool,
+
+    /// fdupes: accepted for compatibility (scans are always recursive)
+    #[arg(short='r', long="recurse", default_value_t = false)]
+    pub recurse: bool,
+
+    /// fdupes: accepted for compatibility (there is no progress indicator)
+    #[arg(short='q', long="quiet", default_value_t = false)]
+    pub quiet: bool,
+
+    /// fdupes: accepted for compatibility (empty files are never reported)
+    #[arg(long="noempty", default_value_t = false)]
+    pub noempty: bool,
+
+    /// Print statistics about the run (files, cache, wasted space, timings) to stderr
+    #[arg(long, default_value_t = false)]
+    pub stats: bool,
+
+    /// With --format ndjson: also stream scan start, progress, error and summary records
+    #[arg(long, default_value_t = false)]
+    pub events: bool,
+
     /// Number of computing threads to use (defaults to total cores)
     #[arg(short, long, hide=HIDE_THREADS)]
     pub threads: Option<usize>,
@@ -41,11 +157,19 @@ pub struct Args {
     /// Verbose output (repeat for more verbosity)
     #[arg(short='v', long="verbose", action = clap::ArgAction::Count, hide=HIDE_VERBOSE)]
     pub verbosity: u8,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
 }
 
 impl Args {
     pub fn new() -> Self {
-        Self::parse()
+        let args = Self::parse();
+        // clap can require an option but not one of its values
+        if args.events && args.format != Some(Format::Ndjson) {
+            Self::command().error(clap::error::ErrorKind::ArgumentConflict, "--events needs --format ndjson").exit();
+        }
+        args
     }
 }
 
@@ -0,0 +1,190 @@
+use crate::types::PathData;
+#[cfg(not(feature = "clap"))]
+use crate::types::Result;
+
+/// Commands that work on the cache file instead of scanning folders
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "clap", derive(clap::Subcommand))]
+pub enum Command {
+    /// Inspect and maintain a cache: the file given with --cache-file, else
+    /// the cache a scan of the folders given with --for uses (the current
+    /// directory by default)
+    Cache {
+        /// Work on the cache of the scans of these folders (repeatable)
+        #[cfg_attr(feature = "clap", arg(long = "for", value_name = "<DIR>", global = true))]
+        folders : Vec<PathData>,
+        #[cfg_attr(feature = "clap", command(subcommand))]
+        command : CacheCommand,
+    },
+}
+
+#[derive(Debug,Clone)]
+#[cfg_attr(feature = "clap", derive(clap::Subcommand))]
+pub enum CacheCommand {
+    /// Drop stale entries from the cache
+    Prune {
+        /// Drop the entries of files that no longer exist
+        #[cfg_attr(feature = "clap", arg(long, default_value_t = false))]
+        missing : bool,
+        /// Drop the entries hashed longer ago than this (30days, 12h...)
+        #[cfg_attr(feature = "clap", arg(long, value_name = "<AGE>"))]
+        max_age : Option<String>,
+    },
+    /// Show the number of entries, the bytes they cover and their age
+    Stats,
+    /// List the entries (hash, size and path), optionally only those under
+    /// the given paths or matching the given glob patterns
+    List {
+        #[cfg_attr(feature = "clap", arg(value_name = "PATTERN"))]
+        patterns : Vec<String>,
+    },
+    /// Show the entries with the given hashes (8 hex digits at least) or paths
+    Lookup {
+        #[cfg_attr(feature = "clap", arg(value_name = "HASH|PATH", required = true))]
+        keys : Vec<String>,
+    },
+    /// Remove the entries under the given paths or matching the given glob patterns
+    Remove {
+        #[cfg_attr(feature = "clap", arg(value_name = "PATTERN", required = true))]
+        patterns : Vec<String>,
+    },
+    /// Merge other cache files into the cache (the entry matching the file,
+    /// else the most recently modified one, wins; conflicts are reported)
+    Merge {
+        #[cfg_attr(feature = "clap", arg(value_name = "CACHE", required = true))]
+        sources : Vec<PathData>,
+    },
+    /// Write the whole cache as JSON
+    Export {
+        /// Write to this file instead of stdout
+        #[cfg_attr(feature = "clap", arg(short, long, value_name = "<FILE>"))]
+        output : Option<PathData>,
+    },
+    /// Remove the digests stored with --xattr from the files in the given folders
+    #[cfg(feature = "xattr")]
+    StripXattr {
+        #[cfg_attr(feature = "clap", arg(value_name = "FOLDER", required = true))]
+        folders : Vec<PathData>,
+    },
+}
+
+impl CacheCommand {
+    /// Tell if the command needs an existing cache file (merge creates it,
+    /// strip-xattr doesn't use it)
+    pub fn reads_cache(&self) -> bool {
+        match self {
+            CacheCommand::Merge { .. } => false,
+            #[cfg(feature = "xattr")]
+            CacheCommand::StripXattr { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+/// Parse the command line (without the program name) of a command for the
+/// parsers that don't handle subcommands. Returns None when the first
+/// argument is not a command name, otherwise the command and the cache
+/// file given with -c/--cache-file if any. Options take their value as
+/// --opt=value or --opt value.
+#[cfg(not(feature = "clap"))]
+pub fn parse(args : &[String]) -> Result<Option<(Command, Option<PathData>)>> {
+    let Some(("cache", rest)) = args.split_first().map(|(first, rest)| (first.as_str(), rest)) else {
+        return Ok(None);
+    };
+    let Some((action, rest)) = rest.split_first() else {
+        return Err(anyhow::format_err!("missing cache command (expected {})",COMMANDS));
+    };
+    let mut options = Options::new(rest, &["-c", "--cache-file", "--for", "--max-age", "-o", "--output"]);
+    let cache_file = options.value(&["-c", "--cache-file"])?.map(PathData::from);
+    let mut folders = vec![];
+    while let Some(folder) = options.value(&["--for"])? {
+        folders.push(PathData::from(folder));
+    }
+    let command = match action.as_str() {
+        "prune" => CacheCommand::Prune {
+            missing : options.flag("--missing"),
+            max_age : options.value(&["--max-age"])?,
+        },
+        "stats" => CacheCommand::Stats,
+        "list" => CacheCommand::List { patterns : options.free() },
+        "lookup" => CacheCommand::Lookup { keys : options.required_free("HASH|PATH")? },
+        "remove" => CacheCommand::Remove { patterns : options.required_free("PATTERN")? },
+        "merge" => CacheCommand::Merge { sources : options.required_free("CACHE")?.into_iter().map(PathData::from).collect() },
+        "export" => CacheCommand::Export { output : options.value(&["-o", "--output"])?.map(PathData::from) },
+        #[cfg(feature = "xattr")]
+        "strip-xattr" => CacheCommand::StripXattr { folders : options.required_free("FOLDER")?.into_iter().map(PathData::from).collect() },
+        _ => return Err(anyhow::format_err!("unknown cache command: {} (expected {})",action,COMMANDS)),
+    };
+    options.finish()?;
+    Ok(Some((Command::Cache { folders, command }, cache_file)))
+}
+
+#[cfg(all(not(feature = "clap"), not(feature = "xattr")))]
+const COMMANDS : &str = "prune, stats, list, lookup, remove, merge or export";
+#[cfg(all(not(feature = "clap"), feature = "xattr"))]
+const COMMANDS : &str = "prune, stats, list, lookup, remove, merge, export or strip-xattr";
+
+/// The options of a command as (name, value) pairs and its other
+/// arguments, consumed as they are read
+#[cfg(not(feature = "clap"))]
+struct Options {
+    options : Vec<(String, Option<String>)>,
+    free : Vec<String>,
+}
+
+#[cfg(not(feature = "clap"))]
+impl Options {
+    /// with_value lists the options that take a value
+    fn new(args : &[String], with_value : &[&str]) -> Self {
+        let mut options = vec![];
+        let mut free = vec![];
+        let mut args = args.iter().peekable();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                free.extend(args.by_ref().cloned());
+            } else if !arg.starts_with('-') || arg == "-" {
+                free.push(arg.clone());
+            } else if let Some((name, value)) = arg.split_once('=') {
+                options.push((name.to_string(), Some(value.to_string())));
+            } else if with_value.contains(&arg.as_str()) {
+                let value = args.next_if(|a| !a.starts_with('-')).cloned();
+                options.push((arg.clone(), value));
+            } else {
+                options.push((arg.clone(), None));
+            }
+        }
+        Self { options, free }
+    }
+    fn take(&mut self, names : &[&str]) -> Option<Option<String>> {
+        let pos = self.options.iter().position(|(name, _)| names.contains(&name.as_str()))?;
+        Some(self.options.remove(pos).1)
+    }
+    fn flag(&mut self, name : &str) -> bool {
+        self.take(&[name]).is_some()
+    }
+    fn value(&mut self, names : &[&str]) -> Result<Option<String>> {
+        match self.take(names) {
+            Some(None) => Err(anyhow::format_err!("missing value for {}",names.join("/"))),
+            value => Ok(value.flatten()),
+        }
+    }
+    fn free(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.free)
+    }
+    fn required_free(&mut self, name : &str) -> Result<Vec<String>> {
+        match self.free() {
+            free if free.is_empty() => Err(anyhow::format_err!("missing {} argument",name)),
+            free => Ok(free),
+        }
+    }
+    /// Fails on the options and arguments that were not read
+    fn finish(self) -> Result<()> {
+        if let Some((name, _)) = self.options.first() {
+            return Err(anyhow::format_err!("unknown option: {}",name));
+        }
+        match self.free.first() {
+            Some(arg) => Err(anyhow::format_err!("unexpected argument: {}",arg)),
+            None => Ok(()),
+        }
+    }
+}
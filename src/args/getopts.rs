@@ -1,20 +1,49 @@
-use crate::{types::PathData, DEFAULT_CACHE_FILE};
+use crate::{types::PathData, output::Format};
+use crate::sort::{SortOrder,GroupKey,PathKey};
+use crate::report::ReportOptions;
+use crate::cache::{CacheCheck,Compression};
+use super::{command,Command};
 use std::process::exit;
 use std::env;
 
 #[derive(Debug)]
 pub struct Args {
     pub folders: Vec<PathData>,
+    pub reference: Vec<PathData>,
     pub disable_cache: bool,
     pub empty_cache: bool,
-    pub cache_file: PathData,
+    pub cache_file: Option<PathData>,
+    pub cache_in_root: bool,
+    pub cache_check: CacheCheck,
+    pub cache_compression: Option<Compression>,
+    pub prune_unseen: bool,
+    pub prune_missing: bool,
+    pub max_age: Option<String>,
+    pub cache_root: Option<PathData>,
+    pub remap: Vec<String>,
+    pub xattr: bool,
     pub normalize: bool,
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    pub settle: Option<u64>,
+    pub sort: SortOrder<GroupKey>,
+    pub sort_paths: SortOrder<PathKey>,
+    pub format: Option<Format>,
+    pub top_pairs: usize,
+    pub print0: bool,
+    pub show_size: bool,
+    pub same_line: bool,
+    pub omit_first: bool,
+    pub summarize: bool,
+    pub events: bool,
+    pub stats: bool,
     pub threads: Option<usize>,
     pub verbosity: u8,
+    pub command: Option<Command>,
 }
 
 fn print_usage(program: &str, opts: getopts::Options) {
-    let brief = format!("Usage: {} FOLDER... [options]", program);
+    let brief = format!("Usage: {} FOLDER... [options]\n       {} cache prune [--missing] [--max-age AGE] [-c FILE]\n       {} cache stats|list [PATTERN...]|lookup HASH|PATH...|remove PATTERN...|merge CACHE...|export [-o FILE] [-c FILE]\n       (the cache commands work on the cache given with -c, else on the cache of the scans of the folders given with --for DIR, the current directory by default)", program, program, program);
     print!("{}", opts.usage(&brief));
 }
 
@@ -22,11 +51,50 @@ impl Args {
     pub fn new() -> Self {
         let args: Vec<String> = env::args().collect();
         let program = args[0].clone();
+        let (command, command_cache_file) = match command::parse(&args[1..]) {
+            Ok(Some((command, cache_file))) => (Some(command), cache_file),
+            Ok(None) => (None, None),
+            Err(e) => {
+                println!("{}",e);
+                exit(1)
+            }
+        };
+        // a command has its own options, the scan options keep their defaults
+        let scan_args = if command.is_some() { &[][..] } else { &args[1..] };
     
         let mut opts = getopts::Options::new();
-        opts.optopt("c", "cache-file", format!("where to store the cache [default: {}]",DEFAULT_CACHE_FILE).as_str(), "FILE");
+        opts.optopt("c", "cache-file", "where to store the cache [default: .fdedup_cache.bin in the first folder if it exists, else a per-user cache in $XDG_CACHE_HOME/fdedup]", "FILE");
+        opts.optflag("", "cache-in-root", "keep the cache in the first folder (.fdedup_cache.bin) instead of the per-user cache");
+        opts.optopt("", "cache-check", "how cached digests are validated: fast (mtime, size), strict (+ inode, device, ctime) or paranoid (+ rehash) [default: strict]", "CHECK");
+        opts.optopt("", "cache-compression", "compression of the cache file: none or zstd (the paths are always prefix compressed) [default: keep the current one]", "TYPE");
+        opts.optflag("", "prune-unseen", "drop the cache entries of files under the scanned folders that were not found");
+        opts.optflag("", "prune-missing", "drop the cache entries of files that no longer exist");
+        opts.optopt("", "max-age", "drop the cache entries hashed longer ago than this (30days, 12h...)", "AGE");
+        opts.optopt("", "cache-root", "store the cached paths relative to this folder, so the cache survives moves and remounts", "DIR");
+        #[cfg(feature = "xattr")]
+        opts.optflag("", "xattr", "also keep the digests in user.fdedup.* extended attributes of the files (reused with --cache-check fast only)");
+        opts.optmulti("", "remap", "read the cached paths starting with OLD as starting with NEW (repeatable)", "OLD=NEW");
+        opts.optopt("", "newer-than", "only scan files modified after this date or duration ago (2023-03-01, 3days, 12h...)", "WHEN");
+        opts.optopt("", "older-than", "only scan files modified before this date or duration ago (2023-03-01, 3days, 12h...)", "WHEN");
+        opts.optopt("", "settle", "skip files modified within the last SECS seconds (probably still being written)", "SECS");
+        opts.optopt("", "sort", "order of the groups: size, wasted, count, path or mtime, optionally followed by :asc or :desc [default: size]", "KEY[:ORDER]");
+        opts.optopt("", "sort-paths", "order of the paths in a group: name, mtime or depth, optionally followed by :asc or :desc [default: name]", "KEY[:ORDER]");
+        opts.optopt("", "format", "output format: text, json, ndjson, csv, tsv, fdupes, print0, dirs or html [default: text, or fdupes with -S, -1, -f, -m or when run as fdupes/jdupes]", "FORMAT");
+        opts.optopt("", "top-pairs", "number of directory pairs sharing the most content shown by the dirs and html formats [default: 20]", "N");
+        opts.optflag("0", "print0", "separate paths with NUL and groups with a double NUL (with -f: only the non-survivors, for xargs -0 rm)");
+        opts.optflag("S", "size", "fdupes: show the size of the duplicate files");
+        opts.optflag("1", "sameline", "fdupes: list each group of duplicates on a single line");
+        opts.optflag("f", "omitfirst", "fdupes: omit the survivor (and the reference files) of each group (also applies to -0)");
+        opts.optflag("", "omit-first", "same as --omitfirst");
+        opts.optflag("m", "summarize", "fdupes: only print a summary of the duplicates");
+        opts.optflag("r", "recurse", "fdupes: accepted for compatibility (scans are always recursive)");
+        opts.optflag("q", "quiet", "fdupes: accepted for compatibility (there is no progress indicator)");
+        opts.optflag("", "noempty", "fdupes: accepted for compatibility (empty files are never reported)");
+        opts.optflag("", "stats", "print statistics about the run (files, cache, wasted space, timings) to stderr");
+        opts.optflag("", "events", "with --format ndjson: also stream scan start, progress, error and summary records");
         #[cfg(feature = "threads")]
-        opts.optopt("t", "threads", "mumber of computing threads to use (defaults to total cores)", "NUM");
+        opts.optopt("t", "threads", "number of computing threads to use (defaults to total cores)", "NUM");
+        opts.optmulti("", "reference", "read-only reference folder: matched against but never acted on (repeatable)", "DIR");
         opts.optflag("h", "help", "print this help menu");
         opts.optflag("d", "disable-cache", "disable the cache");
         opts.optflag("e", "empty-cache", "start with an empty cache");
@@ -34,7 +102,7 @@ impl Args {
         #[cfg(feature = "verbose")]
         opts.optflagmulti("v", "verbose", "verbose output (repeat for more verbosity)");
         
-        let matches = match opts.parse(&args[1..]) {
+        let matches = match opts.parse(scan_args) {
             Ok(m) => { m }
             Err(f) => { 
                 print_usage(&program, opts);
@@ -46,7 +114,8 @@ impl Args {
             print_usage(&program, opts);
             exit(0);
         }
-        let cache_file = PathData::from(matches.opt_str("c").unwrap_or(DEFAULT_CACHE_FILE.to_string()));
+        let cache_file = command_cache_file.or_else(|| matches.opt_str("c").map(PathData::from));
+        let cache_in_root = matches.opt_present("cache-in-root");
         #[cfg(not(feature = "threads"))]
         let threads = None;
         #[cfg(feature = "threads")]
@@ -58,18 +127,128 @@ impl Args {
         let empty_cache = matches.opt_present("e");
         let disable_cache = matches.opt_present("d");
         let normalize = matches.opt_present("n");
+        let events = matches.opt_present("events");
+        let stats = matches.opt_present("stats");
+        let print0 = matches.opt_present("0");
+        let show_size = matches.opt_present("S");
+        let same_line = matches.opt_present("1");
+        let omit_first = matches.opt_present("f") || matches.opt_present("omit-first");
+        let summarize = matches.opt_present("m");
+        let prune_unseen = matches.opt_present("prune-unseen");
+        let prune_missing = matches.opt_present("prune-missing");
+        let max_age = matches.opt_str("max-age");
+        let cache_root = matches.opt_str("cache-root").map(PathData::from);
+        let remap = matches.opt_strs("remap");
+        #[cfg(not(feature = "xattr"))]
+        let xattr = false;
+        #[cfg(feature = "xattr")]
+        let xattr = matches.opt_present("xattr");
+        let newer_than = matches.opt_str("newer-than");
+        let older_than = matches.opt_str("older-than");
+        let settle = match matches.opt_str("settle").map(|s|s.parse::<u64>()).transpose() {
+            Ok(s) => s,
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\ninvalid value for '--settle': {}",e);
+                exit(1)
+            }
+        };
+        let sort = match matches.opt_str("sort").map(|s|s.parse()).transpose() {
+            Ok(s) => s.unwrap_or_default(),
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\n{}",e);
+                exit(1)
+            }
+        };
+        let sort_paths = match matches.opt_str("sort-paths").map(|s|s.parse()).transpose() {
+            Ok(s) => s.unwrap_or_default(),
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\n{}",e);
+                exit(1)
+            }
+        };
+        let cache_check = match matches.opt_str("cache-check").map(|s|s.parse()).transpose() {
+            Ok(c) => c.unwrap_or_default(),
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\n{}",e);
+                exit(1)
+            }
+        };
+        let cache_compression = match matches.opt_str("cache-compression").map(|s|s.parse()).transpose() {
+            Ok(c) => c,
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\n{}",e);
+                exit(1)
+            }
+        };
+        let format = match matches.opt_str("format").map(|s|s.parse::<Format>()).transpose() {
+            Ok(f) => f,
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\n{}",e);
+                exit(1)
+            }
+        };
+        let top_pairs = match matches.opt_str("top-pairs").map(|s|s.parse::<usize>()).transpose() {
+            Ok(n) => n.unwrap_or(ReportOptions::default().top_pairs),
+            Err(e) => {
+                print_usage(&program, opts);
+                println!("\ninvalid value for '--top-pairs': {}",e);
+                exit(1)
+            }
+        };
+        if print0 && format.is_some() {
+            print_usage(&program, opts);
+            println!("\n-0/--print0 can't be used with --format");
+            exit(1)
+        }
+        if events && format != Some(Format::Ndjson) {
+            print_usage(&program, opts);
+            println!("\n--events needs --format ndjson");
+            exit(1)
+        }
+        let reference = matches.opt_strs("reference").iter().map(PathData::from).collect();
         let mut folders : Vec<PathData> = matches.free.iter().map(|s| PathData::from(s)).collect();
         if folders.len() < 1 {
             folders = vec![PathData::from(".")];
         }
         Self {
             folders,
+            reference,
             disable_cache,
             empty_cache,
             cache_file,
+            cache_in_root,
+            cache_check,
+            cache_compression,
+            prune_unseen,
+            prune_missing,
+            max_age,
+            cache_root,
+            remap,
+            xattr,
             normalize,
+            newer_than,
+            older_than,
+            settle,
+            sort,
+            sort_paths,
+            format,
+            top_pairs,
+            print0,
+            show_size,
+            same_line,
+            omit_first,
+            summarize,
+            events,
+            stats,
             threads,
             verbosity,
+            command,
         }
     }
 }
@@ -0,0 +1,645 @@
+use std::borrow::Borrow;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+use serde::{Serialize,Deserialize};
+use anyhow::Context;
+use bincode::Options;
+
+use crate::types::{PathData,FileSize,HashData,Result};
+use crate::hashedfile::{HashedFile,FileStat};
+use crate::duplicates::FileId;
+use crate::pathenc;
+
+mod prune;
+mod lock;
+mod paths;
+pub mod location;
+#[cfg(feature = "xattr")]
+pub mod xattr;
+#[cfg(feature = "redb")]
+pub mod store;
+pub mod maintenance;
+pub use self::prune::{PrunePolicy,PruneReason,PruneStats};
+pub use self::lock::CacheLock;
+pub use self::paths::PathMap;
+
+/// First bytes of every cache file (caches written before the header
+/// existed are headerless bincode, see version 0 below)
+pub const MAGIC : &[u8; 8] = b"FDEDUP\0C";
+/// Format written by this version, older ones are migrated on load
+pub const VERSION : u32 = 1;
+/// Digest stored in the cache (a cache with another one can't be reused)
+pub const HASH_ALGORITHM : &str = "sha512";
+
+const HINT : &str = "delete it, choose another file with --cache-file or rebuild it with --empty-cache";
+
+/// Format history:
+/// - 0: bincode Vec of HashedFile, no header (paths as strings, so caches
+///   with paths that were not valid UTF-8 could not be written)
+/// - 1: MAGIC, version (u32 little endian), then a sequence of records,
+///   each one made of its length and CRC32 (u32 little endian) then its
+///   bincode data: a Header then the entries (raw bytes of the paths,
+///   device, inode, ctime and hashing time of the files). The entries are
+///   sorted by path and each path only stores what differs from the
+///   previous one (see PackedEntry), the header tells how the entry
+///   records are compressed. A damaged record only loses the entries from
+///   there on.
+#[derive(Serialize,Deserialize)]
+struct Header {
+    algorithm : String,
+    root : Option<Vec<u8>>,
+    /// number of entry records that follow
+    entries : u64,
+    compression : Compression,
+}
+
+/// Entry of a version 1 cache: the path of the entry only holds the bytes
+/// that follow the ones it shares with the path of the previous entry
+#[derive(Serialize,Deserialize)]
+struct PackedEntry {
+    shared : u32,
+    entry : Entry,
+}
+
+#[derive(Serialize,Deserialize)]
+struct Entry {
+    path : Vec<u8>,
+    hash : HashData,
+    modified : Option<SystemTime>,
+    size : FileSize,
+    file_id : Option<FileId>,
+    ctime : Option<(i64,i64)>,
+    hashed_at : Option<SystemTime>,
+}
+
+#[derive(Deserialize)]
+struct EntryV0 {
+    path : PathData,
+    hash : HashData,
+    modified : Option<SystemTime>,
+    size : FileSize,
+}
+
+/// Largest record of the current format: there is no reason for an entry
+/// to get near it, a larger length can only come from a damaged cache
+const MAX_RECORD : usize = 1 << 20;
+
+/// Serialize the files in the current format, their paths stored as the map says
+pub fn encode<I>(files : I, map : &PathMap, compression : Compression) -> Result<Vec<u8>> where I : IntoIterator, I::Item : Borrow<HashedFile> {
+    let mut entries : Vec<Entry> = files.into_iter().map(|hf| {
+        let hf = hf.borrow();
+        to_entry(hf, pathenc::to_bytes(&map.store(hf.path())).to_vec())
+    }).collect();
+    entries.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    let root = map.root().map(|root| pathenc::to_bytes(root).to_vec());
+    push_record(&mut bytes, &Header { algorithm : HASH_ALGORITHM.to_string(), root, entries : entries.len() as u64, compression })?;
+    let mut records = vec![];
+    let mut previous = vec![];
+    for mut entry in entries {
+        let shared = previous.iter().zip(&entry.path).take_while(|(a, b)| a == b).count();
+        let rest = entry.path[shared..].to_vec();
+        previous = std::mem::replace(&mut entry.path, rest);
+        push_record(&mut records, &PackedEntry { shared : shared as u32, entry })?;
+    }
+    match compression {
+        Compression::None => bytes.append(&mut records),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => bytes.append(&mut zstd::stream::encode_all(records.as_slice(), 0)?),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => return Err(not_built(compression)),
+    }
+    Ok(bytes)
+}
+
+fn push_record<T : Serialize>(bytes : &mut Vec<u8>, record : &T) -> Result<()> {
+    let data = bincode::serialize(record)?;
+    if data.len() > MAX_RECORD {
+        return Err(anyhow::format_err!("cache record too large ({} bytes)", data.len()));
+    }
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(&data).to_le_bytes());
+    bytes.extend_from_slice(&data);
+    Ok(())
+}
+
+/// Read the next record, checking its length and CRC
+fn read_record<T,R>(reader : &mut R) -> Result<T> where T : serde::de::DeserializeOwned, R : Read {
+    let truncated = |e : std::io::Error| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => anyhow::format_err!("truncated record"),
+        _ => anyhow::Error::from(e),
+    };
+    let mut head = [0; 8];
+    reader.read_exact(&mut head).map_err(truncated)?;
+    let (len, crc) = head.split_at(4);
+    let len = u32::from_le_bytes(len.try_into()?) as usize;
+    if len > MAX_RECORD {
+        return Err(anyhow::format_err!("record length out of range ({} bytes)", len));
+    }
+    let mut data = vec![0; len];
+    reader.read_exact(&mut data).map_err(truncated)?;
+    if crc32fast::hash(&data).to_le_bytes() != crc {
+        return Err(anyhow::format_err!("checksum mismatch"));
+    }
+    bounded(len).reject_trailing_bytes().deserialize(&data).map_err(|e| anyhow::format_err!("invalid record ({})", e))
+}
+
+/// bincode as written by bincode::serialize, reading no more than limit
+/// bytes (so that a damaged length can't trigger a huge allocation)
+fn bounded(limit : usize) -> impl Options {
+    bincode::options().with_fixint_encoding().with_limit(limit as u64)
+}
+
+/// Deserialize a cache written in the current format or in an older one.
+/// The paths are read back as the map says, and the map takes the root
+/// recorded in the cache if it has none.
+pub fn decode(bytes : &[u8], map : &mut PathMap) -> Result<Vec<HashedFile>> {
+    let (root, files) = decode_stored(bytes)?;
+    let relative = root.is_some();
+    map.adopt_root(root);
+    Ok(files.into_iter().map(|hf| {
+        let path = map.restore(hf.path().clone(), relative);
+        hf.with_path(path)
+    }).collect())
+}
+
+/// The recorded root and the entries with their paths as stored
+fn decode_stored(bytes : &[u8]) -> Result<(Option<PathData>, Vec<HashedFile>)> {
+    let Some(rest) = bytes.strip_prefix(MAGIC) else {
+        return Ok((None, decode_v0(bytes)?));
+    };
+    let Some((version, body)) = rest.split_first_chunk::<4>() else {
+        return Err(anyhow::format_err!("truncated cache header"));
+    };
+    match u32::from_le_bytes(*version) {
+        1 => decode_v1(body),
+        version => Err(anyhow::format_err!("cache format version {} is newer than the one this fdedup supports ({}), upgrade fdedup or {}", version, VERSION, HINT)),
+    }
+}
+
+fn decode_v0(bytes : &[u8]) -> Result<Vec<HashedFile>> {
+    let entries : Vec<EntryV0> = bounded(bytes.len()).allow_trailing_bytes().deserialize(bytes)
+        .map_err(|_| anyhow::format_err!("not an fdedup cache or corrupted, {}", HINT))?;
+    Ok(entries.into_iter().map(|e| {
+        HashedFile::from_parts(e.path, e.hash, FileStat { modified : e.modified, size : e.size, ..Default::default() }, None)
+    }).collect())
+}
+
+fn decode_v1(mut bytes : &[u8]) -> Result<(Option<PathData>, Vec<HashedFile>)> {
+    let header : Header = read_record(&mut bytes)
+        .map_err(|e| anyhow::format_err!("corrupted cache header ({:#}), {}", e, HINT))?;
+    check_algorithm(&header.algorithm)?;
+    let root = header.root.map(path_from).transpose()?;
+    let capacity = bytes.len() / 8;
+    let mut reader : Box<dyn Read + '_> = match header.compression {
+        Compression::None => Box::new(bytes),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(bytes)?),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => return Err(not_built(header.compression)),
+    };
+    let mut previous = vec![];
+    let files = read_entries(&mut reader, header.entries, capacity, |packed : PackedEntry| {
+        let shared = packed.shared as usize;
+        if shared > previous.len() {
+            return Err(anyhow::format_err!("invalid path prefix length"));
+        }
+        previous.truncate(shared);
+        previous.extend_from_slice(&packed.entry.path);
+        from_entry(Entry { path : previous.clone(), ..packed.entry })
+    });
+    Ok((root, files))
+}
+
+/// Read the entry records that follow the header. The entries before a
+/// damaged record are kept (with a warning), the cache is written again
+/// in full after the run anyway.
+fn read_entries<R,E,F>(reader : &mut R, count : u64, capacity : usize, mut convert : F) -> Vec<HashedFile>
+        where R : Read, E : serde::de::DeserializeOwned, F : FnMut(E) -> Result<HashedFile> {
+    // each record takes 8 bytes at least, don't trust the count for more
+    let mut files = Vec::with_capacity((count as usize).min(capacity));
+    for i in 0..count {
+        match read_record(reader).and_then(&mut convert) {
+            Ok(hf) => files.push(hf),
+            Err(e) => {
+                eprintln!("Warning: cache damaged at entry {} of {} ({:#}), {} entries salvaged", i + 1, count, e, files.len());
+                return files;
+            },
+        }
+    }
+    if !matches!(reader.read(&mut [0]), Ok(0)) {
+        eprintln!("Warning: unexpected data at the end of the cache, ignored");
+    }
+    files
+}
+
+fn to_entry(hf : &HashedFile, path : Vec<u8>) -> Entry {
+    Entry {
+        path,
+        hash : hf.hash().clone(),
+        modified : hf.modified(),
+        size : hf.size(),
+        file_id : hf.stat().file_id,
+        ctime : hf.stat().ctime,
+        hashed_at : hf.hashed_at(),
+    }
+}
+
+fn from_entry(e : Entry) -> Result<HashedFile> {
+    let stat = FileStat { modified : e.modified, size : e.size, file_id : e.file_id, ctime : e.ctime };
+    Ok(HashedFile::from_parts(path_from(e.path)?, e.hash, stat, e.hashed_at))
+}
+
+fn check_algorithm(algorithm : &str) -> Result<()> {
+    if algorithm != HASH_ALGORITHM {
+        return Err(anyhow::format_err!("the cache holds {} digests but this fdedup uses {}, {}", algorithm, HASH_ALGORITHM, HINT));
+    }
+    Ok(())
+}
+
+fn path_from(bytes : Vec<u8>) -> Result<PathData> {
+    pathenc::from_bytes(bytes).ok_or_else(|| anyhow::format_err!("the cache holds a path that is not valid on this platform, {}", HINT))
+}
+
+/// Write the files to a cache file. The data goes to a temporary file
+/// next to it which then replaces it, so a crash never leaves a truncated cache.
+/// A store (see store::CacheStore) gets all its entries replaced, so the
+/// files must be the ones read from it.
+pub fn write<P,I>(fname : P, files : I, map : &PathMap, compression : Compression) -> Result<()> where P : AsRef<Path>, I : IntoIterator, I::Item : Borrow<HashedFile> {
+    let fname = fname.as_ref();
+    #[cfg(feature = "redb")]
+    if store::is_store(fname) {
+        let files : Vec<_> = files.into_iter().collect();
+        let mut map = map.clone();
+        return store::CacheStore::open(fname, &mut map)?.replace(files.iter().map(|hf| hf.borrow()), &map);
+    }
+    let bytes = encode(files, map, compression)?;
+    let tmp = sibling(fname, &format!(".tmp.{}", std::process::id()));
+    let result = write_synced(&tmp, &bytes).and_then(|_| std::fs::rename(&tmp, fname));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result.with_context(|| format!("could not write cache file {}", fname.display()))
+}
+
+fn write_synced(fname : &Path, bytes : &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut file = std::fs::File::create(fname)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+/// The path with a suffix added to its file name
+fn sibling(fname : &Path, suffix : &str) -> PathData {
+    let mut name = fname.as_os_str().to_owned();
+    name.push(suffix);
+    PathData::from(name)
+}
+
+/// Read a cache file, migrating it from an older format if needed (see
+/// decode). A store (see store::CacheStore) is loaded as a whole.
+pub fn read<P>(fname : P, map : &mut PathMap) -> Result<Vec<HashedFile>> where P : AsRef<Path> {
+    let fname = fname.as_ref();
+    #[cfg(feature = "redb")]
+    if fname.exists() && store::is_store(fname) {
+        return store::CacheStore::open(fname, map)?.entries(map);
+    }
+    let bytes = std::fs::read(fname).with_context(|| format!("could not read cache file {}", fname.display()))?;
+    decode(&bytes, map).with_context(|| format!("could not load cache file {}", fname.display()))
+}
+
+/// The compression of an existing cache file (none when it has none or
+/// can't be read), so that rewriting a cache keeps it
+pub fn compression_of<P>(fname : P) -> Compression where P : AsRef<Path> {
+    let read = || -> Result<Compression> {
+        let mut file = std::io::BufReader::new(std::fs::File::open(fname)?);
+        let mut start = [0; 12];
+        file.read_exact(&mut start)?;
+        if start[..8] != MAGIC[..] || start[8..] != VERSION.to_le_bytes() {
+            return Ok(Compression::None);
+        }
+        Ok(read_record::<Header,_>(&mut file)?.compression)
+    };
+    read().unwrap_or_default()
+}
+
+/// How the entry records of a cache file are compressed (recorded in its header)
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default,Serialize,Deserialize)]
+#[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Compression {
+    /// no compression (the paths still only store what differs from the previous one)
+    #[default]
+    None,
+    /// zstd stream (needs the zstd feature)
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Compression::None),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(anyhow::format_err!("unknown cache compression: {} (expected none or zstd)",s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Compression::None => "none",
+            Compression::Zstd => "zstd",
+        })
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+fn not_built(compression : Compression) -> anyhow::Error {
+    anyhow::format_err!("{} cache compression is not supported by this fdedup (built without the {} feature), {}", compression, compression, HINT)
+}
+
+/// How much of the metadata must match for a cached digest to be reused
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum CacheCheck {
+    /// same modification time and size
+    Fast,
+    /// same modification time, size, device, inode and ctime
+    #[default]
+    Strict,
+    /// same checks as strict, then the file is hashed anyway and the
+    /// cached digest is only used to detect stale entries
+    Paranoid,
+}
+
+impl FromStr for CacheCheck {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "fast" => Ok(CacheCheck::Fast),
+            "strict" => Ok(CacheCheck::Strict),
+            "paranoid" => Ok(CacheCheck::Paranoid),
+            _ => Err(anyhow::format_err!("unknown cache check: {} (expected fast, strict or paranoid)",s)),
+        }
+    }
+}
+
+/// Why a cached digest was not reused
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum Invalidation {
+    Mtime,
+    Size,
+    /// the path now leads to another file (device or inode changed)
+    FileId,
+    Ctime,
+    /// the cache entry lacks metadata required by the check
+    /// (written by an older version or on another platform)
+    Unverified,
+    /// the metadata matched but the content had changed (paranoid only)
+    Content,
+}
+
+impl Invalidation {
+    pub const COUNT : usize = 6;
+}
+
+/// Outcome of looking a file up in the cache
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum CacheLookup {
+    /// the cached digest can be reused
+    Hit,
+    /// no entry for the path
+    Miss,
+    /// there is an entry but it no longer applies
+    Invalid(Invalidation),
+    /// the entry looks valid but the file must be hashed again and
+    /// compared to this digest (paranoid)
+    Verify(HashData),
+}
+
+impl CacheCheck {
+    /// Compare a cache entry with the current metadata of the file
+    pub fn lookup(&self, cached : &HashedFile, current : &FileStat) -> CacheLookup {
+        let old = cached.stat();
+        if old.modified.is_none() || current.modified.is_none() {
+            return CacheLookup::Invalid(Invalidation::Unverified);
+        }
+        if old.modified != current.modified {
+            return CacheLookup::Invalid(Invalidation::Mtime);
+        }
+        if old.size != current.size {
+            return CacheLookup::Invalid(Invalidation::Size);
+        }
+        if *self == CacheCheck::Fast {
+            return CacheLookup::Hit;
+        }
+        // without inodes and ctimes on this platform, mtime and size are all there is
+        if current.file_id.is_some() || current.ctime.is_some() {
+            if old.file_id.is_none() || old.ctime.is_none() {
+                return CacheLookup::Invalid(Invalidation::Unverified);
+            }
+            if old.file_id != current.file_id {
+                return CacheLookup::Invalid(Invalidation::FileId);
+            }
+            if old.ctime != current.ctime {
+                return CacheLookup::Invalid(Invalidation::Ctime);
+            }
+        }
+        match self {
+            CacheCheck::Paranoid => CacheLookup::Verify(cached.hash().clone()),
+            _ => CacheLookup::Hit,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration,UNIX_EPOCH};
+
+    fn time(secs : u64) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::new(secs, 123))
+    }
+
+    fn file(path : &str, byte : u8) -> HashedFile {
+        let stat = FileStat { modified : time(1_000 + byte as u64), size : byte as u64, file_id : Some((1, byte as u64)), ctime : Some((2_000, byte as i64)) };
+        HashedFile::from_parts(PathData::from(path), vec![byte; 64], stat, time(3_000))
+    }
+
+    fn files() -> Vec<HashedFile> {
+        vec![file("/data/a/one", 1), file("/data/a/two", 2), file("/data/b", 3), file("/other", 4)]
+    }
+
+    /// sorted by path
+    fn fields(files : &[HashedFile]) -> Vec<(PathData, HashData, FileStat, Option<SystemTime>)> {
+        let mut fields : Vec<_> = files.iter().map(|hf| (hf.path().clone(), hf.hash().clone(), *hf.stat(), hf.hashed_at())).collect();
+        fields.sort_by(|a, b| a.0.cmp(&b.0));
+        fields
+    }
+
+    /// The entries of an older cache, checked to survive being written
+    /// back in the current format
+    fn migrate(bytes : &[u8]) -> Vec<HashedFile> {
+        let decoded = decode(bytes, &mut PathMap::default()).unwrap();
+        let encoded = encode(&decoded, &PathMap::default(), Compression::None).unwrap();
+        assert_eq!(fields(&decode(&encoded, &mut PathMap::default()).unwrap()), fields(&decoded));
+        decoded
+    }
+
+    fn with_header(version : u32, body : &[u8]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    /// the fields of the files that version 0 records
+    fn v0_fields() -> Vec<HashedFile> {
+        files().into_iter().map(|hf| {
+            let stat = FileStat { file_id : None, ctime : None, ..*hf.stat() };
+            HashedFile::from_parts(hf.path().clone(), hf.hash().clone(), stat, None)
+        }).collect()
+    }
+
+    #[test]
+    fn header() {
+        let bytes = encode(files(), &PathMap::default(), Compression::None).unwrap();
+        assert_eq!(bytes[..12], with_header(VERSION, &[])[..]);
+        assert_eq!(fields(&decode(&bytes, &mut PathMap::default()).unwrap()), fields(&files()));
+    }
+
+    #[test]
+    fn v0() {
+        let entries : Vec<_> = files().iter().map(|hf| (hf.path().clone(), hf.hash().clone(), hf.modified(), hf.size())).collect();
+        let bytes = bincode::serialize(&entries).unwrap();
+        assert_eq!(fields(&migrate(&bytes)), fields(&v0_fields()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_and_newline_paths() {
+        use std::os::unix::ffi::OsStrExt;
+        let odd = [PathData::from(std::ffi::OsStr::from_bytes(b"/data/caf\xe9")), PathData::from("/data/two\nlines")];
+        let files : Vec<HashedFile> = odd.iter().map(|p| HashedFile::from_parts(p.clone(), vec![1; 64], FileStat::default(), None)).collect();
+        let bytes = encode(&files, &PathMap::default(), Compression::None).unwrap();
+        assert_eq!(fields(&decode(&bytes, &mut PathMap::default()).unwrap()), fields(&files));
+    }
+
+    #[test]
+    fn rejects_newer_versions_and_other_algorithms() {
+        let error = decode(&with_header(VERSION + 1, &[]), &mut PathMap::default()).unwrap_err();
+        assert!(error.to_string().contains("is newer than"), "{}", error);
+        let mut bytes = with_header(VERSION, &[]);
+        push_record(&mut bytes, &Header { algorithm : "md5".to_string(), root : None, entries : 0, compression : Compression::None }).unwrap();
+        let error = decode(&bytes, &mut PathMap::default()).unwrap_err();
+        assert!(error.to_string().contains("md5"), "{}", error);
+        assert!(decode(&MAGIC[..], &mut PathMap::default()).is_err());
+        assert!(decode(b"garbage", &mut PathMap::default()).is_err());
+    }
+
+    #[test]
+    fn records_the_root() {
+        let mut map = PathMap::default();
+        map.set_root(Some(PathData::from("/data")));
+        let bytes = encode(files(), &map, Compression::None).unwrap();
+        let (root, stored) = decode_stored(&bytes).unwrap();
+        assert_eq!(root, Some(PathData::from("/data")));
+        let paths : Vec<&PathData> = stored.iter().map(|hf| hf.path()).collect();
+        assert_eq!(paths, ["/other", "a/one", "a/two", "b"].map(PathData::from).iter().collect::<Vec<_>>());
+        let mut map = PathMap::default();
+        assert_eq!(fields(&decode(&bytes, &mut map).unwrap()), fields(&files()));
+        assert_eq!(map.root(), Some(Path::new("/data")));
+    }
+
+    #[test]
+    fn salvages_the_entries_before_a_damaged_record() {
+        let bytes = encode(files(), &PathMap::default(), Compression::None).unwrap();
+        // flip a byte of the data of the last record
+        let mut damaged = bytes.clone();
+        *damaged.last_mut().unwrap() ^= 1;
+        assert_eq!(fields(&decode(&damaged, &mut PathMap::default()).unwrap()), fields(&files()[..3]));
+        // cut in the middle of the third record
+        let record = bytes.len() - bytes.len() / 5;
+        assert!(decode(&bytes[..record], &mut PathMap::default()).unwrap().len() < 4);
+        // a length out of range
+        let mut body = &bytes[12..];
+        read_record::<Header,_>(&mut body).unwrap();
+        let first = bytes.len() - body.len();
+        let mut damaged = bytes.clone();
+        damaged[first..first + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decode(&damaged, &mut PathMap::default()).unwrap().is_empty());
+        // trailing data is ignored
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(decode(&longer, &mut PathMap::default()).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn rejects_a_damaged_header() {
+        let mut bytes = encode(files(), &PathMap::default(), Compression::None).unwrap();
+        bytes[20] ^= 1;
+        let error = decode(&bytes, &mut PathMap::default()).unwrap_err();
+        assert!(error.to_string().contains("corrupted cache header"), "{}", error);
+    }
+
+    #[test]
+    fn read_record_checks() {
+        let mut bytes = vec![];
+        push_record(&mut bytes, &42u64).unwrap();
+        assert_eq!(read_record::<u64,_>(&mut bytes.as_slice()).unwrap(), 42);
+        let error = read_record::<u64,_>(&mut &bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.to_string(), "truncated record");
+        bytes[8] ^= 1;
+        let error = read_record::<u64,_>(&mut bytes.as_slice()).unwrap_err();
+        assert_eq!(error.to_string(), "checksum mismatch");
+        assert!(push_record(&mut vec![], &vec![0u8; MAX_RECORD]).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = encode(files(), &PathMap::default(), Compression::None).unwrap();
+        assert_eq!(fields(&decode(&bytes, &mut PathMap::default()).unwrap()), fields(&files()));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        let bytes = encode(files(), &PathMap::default(), Compression::Zstd).unwrap();
+        assert_eq!(fields(&decode(&bytes, &mut PathMap::default()).unwrap()), fields(&files()));
+    }
+
+    #[test]
+    fn sorts_and_shares_path_prefixes() {
+        let mut reversed = files();
+        reversed.reverse();
+        let bytes = encode(&reversed, &PathMap::default(), Compression::None).unwrap();
+        let mut body = &bytes[12..];
+        let header : Header = read_record(&mut body).unwrap();
+        assert_eq!(header.entries, 4);
+        let packed : Vec<(u32, Vec<u8>)> = (0..4).map(|_| read_record::<PackedEntry,_>(&mut body).unwrap())
+            .map(|p| (p.shared, p.entry.path)).collect();
+        assert_eq!(packed, [(0, b"/data/a/one".to_vec()), (8, b"two".to_vec()), (6, b"b".to_vec()), (1, b"other".to_vec())]);
+        assert_eq!(fields(&decode(&bytes, &mut PathMap::default()).unwrap()), fields(&files()));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn salvages_a_truncated_zstd_cache() {
+        // digests that don't compress, enough of them for several zstd blocks
+        let mut seed = 1u64;
+        let many : Vec<HashedFile> = (0..5000).map(|i| {
+            let hash = (0..64).map(|_| { seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1); (seed >> 56) as u8 }).collect();
+            HashedFile::from_parts(PathData::from(format!("/data/{:04}", i)), hash, FileStat::default(), None)
+        }).collect();
+        let bytes = encode(&many, &PathMap::default(), Compression::Zstd).unwrap();
+        let salvaged = decode(&bytes[..bytes.len() * 3 / 4], &mut PathMap::default()).unwrap();
+        assert!(!salvaged.is_empty() && salvaged.len() < many.len(), "{} entries salvaged", salvaged.len());
+        assert_eq!(fields(&salvaged), fields(&many[..salvaged.len()]));
+    }
+
+}
@@ -0,0 +1,134 @@
+use std::ffi::OsStr;
+use std::path::Path;
+use sha2::{Sha512,Digest};
+
+use crate::types::PathData;
+use crate::verbose::vprintln;
+use crate::DEFAULT_CACHE_FILE;
+use super::paths::absolute;
+
+/// Name of the folder holding the per-user caches
+const USER_DIR : &str = "fdedup";
+/// Prefix of the names of the files fdedup writes in the scanned trees
+/// (caches and their lock and temporary files)
+const ARTIFACT_PREFIX : &str = ".fdedup";
+
+/// The cache file used when none is given, and the root its paths are
+/// stored relative to (see PathMap)
+#[derive(Debug,Clone)]
+pub struct Location {
+    pub file : PathData,
+    pub root : PathData,
+}
+
+/// Find the cache of the folders when no cache file is given. A cache
+/// kept inside the first folder (DEFAULT_CACHE_FILE) is used if it exists
+/// or if in_root is set, otherwise the per-user cache of the folders:
+/// $XDG_CACHE_HOME/fdedup (~/.cache/fdedup by default), one file per set
+/// of folders, named after their canonical paths so that it is found
+/// whatever the working directory and the way the folders are given.
+/// Without a per-user cache folder (no HOME, as under wasi), the cache
+/// stays in the current directory.
+pub fn discover(folders : &[PathData], in_root : bool) -> Location {
+    let root = folders.first().cloned().unwrap_or_else(|| PathData::from("."));
+    let in_tree = root.join(DEFAULT_CACHE_FILE);
+    if in_root || in_tree.exists() {
+        return Location { file : in_tree, root };
+    }
+    let file = user_dir()
+        .filter(|dir| match std::fs::create_dir_all(dir) {
+            Ok(()) => true,
+            Err(_e) => {
+                vprintln!(1,"could not create {}: {}",dir.display(),_e);
+                false
+            },
+        })
+        .map(|dir| dir.join(user_file_name(folders)))
+        .unwrap_or_else(|| PathData::from(DEFAULT_CACHE_FILE));
+    Location { file, root }
+}
+
+/// The folder holding the per-user caches, if the environment tells where it is
+pub fn user_dir() -> Option<PathData> {
+    let absolute_var = |name| std::env::var_os(name).map(PathData::from).filter(|p| p.is_absolute());
+    absolute_var("XDG_CACHE_HOME")
+        .or_else(|| absolute_var("HOME").map(|home| home.join(".cache")))
+        .map(|dir| dir.join(USER_DIR))
+}
+
+/// The cache files in the per-user cache folder, sorted
+pub fn user_caches() -> Vec<PathData> {
+    let Some(entries) = user_dir().and_then(|dir| std::fs::read_dir(dir).ok()) else {
+        return vec![];
+    };
+    let mut caches : Vec<PathData> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bin" || ext == "redb"))
+        .collect();
+    caches.sort();
+    caches
+}
+
+/// Name of one of the folders followed by a digest of the canonical
+/// paths of all of them (sorted, so their order doesn't matter)
+fn user_file_name(folders : &[PathData]) -> String {
+    let mut canonical : Vec<PathData> = folders.iter()
+        .map(|f| std::fs::canonicalize(f).unwrap_or_else(|_| absolute(f)))
+        .collect();
+    canonical.sort();
+    canonical.dedup();
+    let mut hasher = Sha512::new();
+    for path in &canonical {
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update([0]);
+    }
+    let digest = hex::encode(&hasher.finalize()[..8]);
+    let label : String = canonical.first()
+        .and_then(|p| p.file_name())
+        .map(|name| name.to_string_lossy().chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect())
+        .unwrap_or_else(|| "root".to_string());
+    format!("{}-{}.bin",label,digest)
+}
+
+/// The files and folders the scans never go into: the files fdedup
+/// writes (named .fdedup*), the per-user cache folder and the cache files
+/// given explicitly, along with their lock and temporary files
+#[derive(Debug,Clone,Default)]
+pub struct Artifacts {
+    /// absolute
+    paths : Vec<PathData>,
+}
+
+impl Artifacts {
+    pub fn new() -> Self {
+        Self { paths : user_dir().into_iter().collect() }
+    }
+    /// Also skip this file (and its siblings with a suffix: <file>.lock, <file>.tmp.*)
+    pub fn add<P>(&mut self, path : P) where P : AsRef<Path> {
+        self.paths.push(absolute(path.as_ref()));
+    }
+    /// Tell if the walk must skip the file or folder
+    pub fn contains(&self, path : &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return false;
+        };
+        if name.as_encoded_bytes().starts_with(ARTIFACT_PREFIX.as_bytes()) {
+            return true;
+        }
+        // the names are compared first, absolute paths are only built for the candidates
+        let candidates : Vec<&PathData> = self.paths.iter().filter(|p| p.file_name().is_some_and(|n| same_or_sibling(name, n))).collect();
+        if candidates.is_empty() {
+            return false;
+        }
+        let path = absolute(path);
+        candidates.into_iter().any(|p| path.parent() == p.parent())
+    }
+}
+
+/// name is the artifact name, or that name followed by a dotted suffix
+fn same_or_sibling(name : &OsStr, artifact : &OsStr) -> bool {
+    match name.as_encoded_bytes().strip_prefix(artifact.as_encoded_bytes()) {
+        Some(rest) => rest.is_empty() || rest.starts_with(b"."),
+        None => false,
+    }
+}
@@ -0,0 +1,33 @@
+use std::fs::{File,OpenOptions};
+use std::io::ErrorKind;
+use std::path::Path;
+use anyhow::Context;
+
+use crate::types::Result;
+use crate::verbose::vprintln;
+
+/// Advisory lock held by a process while it reads or replaces a cache file,
+/// released when dropped. The lock is taken on a separate "<cache>.lock"
+/// file since the cache file itself gets replaced when it is written.
+#[derive(Debug)]
+pub struct CacheLock {
+    _file : File,
+}
+
+impl CacheLock {
+    /// Wait until no other process holds the lock on the cache file.
+    /// On platforms without file locking, the lock is a no-op.
+    pub fn acquire<P>(fname : P) -> Result<Self> where P : AsRef<Path> {
+        let fname = super::sibling(fname.as_ref(), ".lock");
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(&fname)
+            .with_context(|| format!("could not create lock file {}", fname.display()))?;
+        match file.lock() {
+            Ok(()) => {},
+            Err(e) if e.kind() == ErrorKind::Unsupported => {
+                vprintln!(1,"file locking is not supported, {} is not locked",fname.display());
+            },
+            Err(e) => return Err(e).with_context(|| format!("could not lock {}", fname.display())),
+        }
+        Ok(Self { _file : file })
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration,SystemTime};
+use serde::Serialize;
+
+use crate::types::{PathData,FileSize,Result};
+use crate::dedupstate::DedupState;
+use crate::hashedfile::{HashedFile,FileStat};
+use crate::duplicates::FileId;
+use crate::pathenc;
+use super::{PrunePolicy,PruneStats,CacheLock,PathMap,CacheCheck,CacheLookup,Compression,compression_of};
+
+/// Drop the stale entries of a cache file without scanning anything.
+/// Without a scan no file counts as seen, so policy.unseen is ignored.
+pub fn prune<P>(fname : P, policy : &PrunePolicy) -> Result<PruneStats> where P : AsRef<Path> {
+    let fname = fname.as_ref();
+    let policy = PrunePolicy { unseen : false, ..policy.clone() };
+    let now = SystemTime::now();
+    let _lock = CacheLock::acquire(fname)?;
+    let mut state = DedupState::new();
+    let mut map = PathMap::default();
+    state.read_cache(fname, &mut map)?;
+    let mut stats = PruneStats::default();
+    state.prune(|hf, seen| {
+        let reason = policy.check(hf, seen, false, now);
+        stats.add(reason);
+        reason.is_some()
+    });
+    if stats.pruned() > 0 {
+        state.write_cache(fname, &map, compression_of(fname))?;
+    }
+    Ok(stats)
+}
+
+/// Selects cache entries by path. An entry matches a pattern when its path
+/// is the pattern or is below it, or when the glob pattern (*, ?, [...])
+/// matches the whole path. An empty filter matches everything.
+#[derive(Debug,Clone,Default)]
+pub struct PathFilter {
+    patterns : Vec<(std::path::PathBuf, Option<glob::Pattern>)>,
+}
+
+impl PathFilter {
+    /// The patterns may be escaped the way the outputs write paths.
+    /// Patterns that are not valid globs only match as paths.
+    pub fn new<S : AsRef<str>>(patterns : &[S]) -> Result<Self> {
+        let patterns = patterns.iter().map(|p| {
+            let p = p.as_ref();
+            Ok((pathenc::unescape(p)?, glob::Pattern::new(p).ok()))
+        }).collect::<Result<_>>()?;
+        Ok(Self { patterns })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+    pub fn matches(&self, path : &Path) -> bool {
+        self.is_empty() || self.patterns.iter().any(|(prefix, glob)| {
+            path.starts_with(prefix) || glob.as_ref().is_some_and(|g| g.matches(&pathenc::escape(path)))
+        })
+    }
+}
+
+/// Age of the cache entries, from the time they were hashed
+#[derive(Debug,Clone,Copy,Default,Serialize)]
+pub struct AgeDistribution {
+    pub day : u64,
+    pub week : u64,
+    pub month : u64,
+    pub year : u64,
+    pub older : u64,
+    /// entries migrated from caches that did not record the hashing time
+    pub unknown : u64,
+}
+
+/// Summary of the content of a cache file
+#[derive(Debug,Clone,Default,Serialize)]
+pub struct CacheStats {
+    pub entries : u64,
+    /// total size of the files covered by the cache
+    pub bytes : FileSize,
+    /// number of different contents
+    pub distinct_hashes : u64,
+    pub ages : AgeDistribution,
+    /// size of the cache file itself
+    pub file_size : FileSize,
+    pub compression : Compression,
+}
+
+impl AgeDistribution {
+    const DAY : Duration = Duration::from_secs(24 * 3600);
+
+    fn add(&mut self, hashed_at : Option<SystemTime>, now : SystemTime) {
+        let Some(at) = hashed_at else {
+            self.unknown += 1;
+            return;
+        };
+        // hashed in the future (clock change): count as recent
+        let age = now.duration_since(at).unwrap_or_default();
+        let bucket = match age {
+            age if age < Self::DAY => &mut self.day,
+            age if age < 7 * Self::DAY => &mut self.week,
+            age if age < 30 * Self::DAY => &mut self.month,
+            age if age < 365 * Self::DAY => &mut self.year,
+            _ => &mut self.older,
+        };
+        *bucket += 1;
+    }
+}
+
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{} cache entries, {} bytes, {} distinct contents", self.entries, self.bytes, self.distinct_hashes)?;
+        writeln!(f, "cache file: {} bytes, compression: {}", self.file_size, self.compression)?;
+        let a = &self.ages;
+        write!(f, "hashed within a day: {}, a week: {}, a month: {}, a year: {}, older: {}, unknown: {}",
+            a.day, a.week, a.month, a.year, a.older, a.unknown)
+    }
+}
+
+/// The entries of a cache file, with the paths relative to the root it
+/// records (if any) made absolute
+fn load(fname : &Path) -> Result<Vec<HashedFile>> {
+    let mut state = DedupState::new();
+    state.read_cache(fname, &mut PathMap::default())?;
+    Ok(state.cached_files())
+}
+
+/// Count the entries of a cache file, the bytes they cover and their age
+pub fn stats<P>(fname : P) -> Result<CacheStats> where P : AsRef<Path> {
+    let now = SystemTime::now();
+    let fname = fname.as_ref();
+    let files = load(fname)?;
+    let mut stats = CacheStats {
+        entries : files.len() as u64,
+        file_size : std::fs::metadata(fname)?.len(),
+        compression : compression_of(fname),
+        ..Default::default()
+    };
+    let mut hashes = std::collections::HashSet::new();
+    for hf in &files {
+        stats.bytes += hf.size();
+        stats.ages.add(hf.hashed_at(), now);
+        hashes.insert(hf.hash());
+    }
+    stats.distinct_hashes = hashes.len() as u64;
+    Ok(stats)
+}
+
+/// The entries of a cache file selected by the filter, sorted by path
+pub fn list<P>(fname : P, filter : &PathFilter) -> Result<Vec<HashedFile>> where P : AsRef<Path> {
+    Ok(load(fname.as_ref())?.into_iter().filter(|hf| filter.matches(hf.path())).collect())
+}
+
+/// The entries of a cache file for each key: a hash (hexadecimal, at least
+/// 8 digits, prefixes allowed) or a path (escaped or not, relative paths
+/// are also tried from the current directory)
+pub fn lookup<P,S>(fname : P, keys : &[S]) -> Result<Vec<HashedFile>> where P : AsRef<Path>, S : AsRef<str> {
+    let files = load(fname.as_ref())?;
+    let mut found = vec![];
+    for key in keys {
+        let key = key.as_ref();
+        if key.len() >= 8 && key.chars().all(|c| c.is_ascii_hexdigit()) {
+            let key = key.to_ascii_lowercase();
+            found.extend(files.iter().filter(|hf| hex::encode(hf.hash()).starts_with(&key)).cloned());
+        }
+        let path = pathenc::unescape(key)?;
+        let absolute = std::path::absolute(&path).ok();
+        found.extend(files.iter()
+            .filter(|hf| *hf.path() == path || absolute.as_ref().is_some_and(|a| hf.path() == a))
+            .cloned());
+    }
+    Ok(found)
+}
+
+/// Drop the entries selected by the filter from a cache file, returns
+/// their number
+pub fn remove<P>(fname : P, filter : &PathFilter) -> Result<u64> where P : AsRef<Path> {
+    if filter.is_empty() {
+        return Err(anyhow::format_err!("no pattern given: refusing to empty the cache"));
+    }
+    let fname = fname.as_ref();
+    let _lock = CacheLock::acquire(fname)?;
+    let mut state = DedupState::new();
+    let mut map = PathMap::default();
+    state.read_cache(fname, &mut map)?;
+    let mut removed = 0;
+    state.prune(|hf, _| {
+        let matches = filter.matches(hf.path());
+        removed += matches as u64;
+        matches
+    });
+    if removed > 0 {
+        state.write_cache(fname, &map, compression_of(fname))?;
+    }
+    Ok(removed)
+}
+
+/// Two caches holding different digests for the same path
+#[derive(Debug,Clone)]
+pub struct MergeConflict {
+    pub path : PathData,
+    /// the cache file the kept entry comes from, and its modification time
+    pub kept : (PathData, Option<SystemTime>),
+    pub dropped : (PathData, Option<SystemTime>),
+    /// the kept entry matches the file as it is now (size and modification time)
+    pub validated : bool,
+}
+
+/// What merge did
+#[derive(Debug,Clone,Default)]
+pub struct MergeReport {
+    /// entries in the merged cache
+    pub entries : u64,
+    /// entries for paths the target did not have
+    pub added : u64,
+    /// entries that replaced an older one for the same path
+    pub replaced : u64,
+    pub conflicts : Vec<MergeConflict>,
+}
+
+impl std::fmt::Display for MergeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let time = |t : &Option<SystemTime>| t.map_or("unknown mtime".to_string(), |t| humantime::format_rfc3339_seconds(t).to_string());
+        for c in &self.conflicts {
+            writeln!(f, "conflict: {}: kept {} from {}{}, dropped {} from {}",
+                pathenc::escape_line(&c.path),
+                time(&c.kept.1), pathenc::escape_line(&c.kept.0), if c.validated { " (matches the file)" } else { "" },
+                time(&c.dropped.1), pathenc::escape_line(&c.dropped.0))?;
+        }
+        write!(f, "{} cache entries ({} added, {} replaced, {} conflicts)", self.entries, self.added, self.replaced, self.conflicts.len())
+    }
+}
+
+/// Merge cache files into the target one (created if needed). When the
+/// caches disagree about a path, the entry that matches the file as it is
+/// now wins, otherwise the one with the newest modification time (the
+/// target wins ties). The entries with different digests are reported as
+/// conflicts. The paths of each cache are resolved against the root it
+/// records, the merged cache keeps the root and the compression of the
+/// target (or of the first source that has one).
+pub fn merge<P,Q>(target : P, sources : &[Q]) -> Result<MergeReport> where P : AsRef<Path>, Q : AsRef<Path> {
+    let target = target.as_ref();
+    let _lock = CacheLock::acquire(target)?;
+    let mut map = PathMap::default();
+    let mut compression = compression_of(target);
+    let mut merged : HashMap<PathData, (HashedFile, &Path)> = HashMap::new();
+    if target.exists() {
+        for hf in super::read(target, &mut map)? {
+            merged.insert(hf.path().clone(), (hf, target));
+        }
+    }
+    let mut report = MergeReport::default();
+    for source in sources {
+        let source = source.as_ref();
+        let mut source_map = PathMap::default();
+        let files = super::read(source, &mut source_map)?;
+        map.adopt_root(source_map.root().map(Path::to_owned));
+        if compression == Compression::None {
+            compression = compression_of(source);
+        }
+        for hf in files {
+            let Some((old, old_source)) = merged.get_mut(hf.path()) else {
+                report.added += 1;
+                merged.insert(hf.path().clone(), (hf, source));
+                continue;
+            };
+            if old.hash() == hf.hash() && old.stat() == hf.stat() {
+                continue;
+            }
+            let current = std::fs::metadata(hf.path()).ok().map(|meta| FileStat::from_metadata(&meta));
+            let validated = |hf : &HashedFile| current.as_ref()
+                .is_some_and(|stat| CacheCheck::Fast.lookup(hf, stat) == CacheLookup::Hit);
+            let (old_valid, new_valid) = (validated(old), validated(&hf));
+            let take = (new_valid, hf.modified()) > (old_valid, old.modified());
+            if old.hash() != hf.hash() {
+                let old_side = (old_source.to_path_buf(), old.modified());
+                let new_side = (source.to_path_buf(), hf.modified());
+                let (kept, dropped) = if take { (new_side, old_side) } else { (old_side, new_side) };
+                report.conflicts.push(MergeConflict { path : hf.path().clone(), kept, dropped, validated : old_valid || new_valid });
+            }
+            if take {
+                report.replaced += 1;
+                *old = hf;
+                *old_source = source;
+            }
+        }
+    }
+    report.entries = merged.len() as u64;
+    let mut files : Vec<HashedFile> = merged.into_values().map(|(hf, _)| hf).collect();
+    files.sort_by(|a, b| a.path().cmp(b.path()));
+    super::write(target, &files, &map, compression)?;
+    Ok(report)
+}
+
+/// Write an entry as a line: hash, size and path (escaped if needed)
+pub fn write_entry<W : Write>(out : &mut W, hf : &HashedFile) -> Result<()> {
+    writeln!(out, "{} {:>12} {}", hex::encode(hf.hash()), hf.size(), pathenc::escape_line(hf.path()))?;
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Export<'a> {
+    version : u32,
+    algorithm : &'a str,
+    entries : Vec<ExportEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct ExportEntry<'a> {
+    path : std::borrow::Cow<'a, str>,
+    hash : String,
+    size : FileSize,
+    modified : Option<String>,
+    hashed_at : Option<String>,
+    file_id : Option<FileId>,
+    ctime : Option<(i64,i64)>,
+}
+
+/// Write the whole content of a cache file as JSON
+pub fn export<P,W>(fname : P, out : W) -> Result<()> where P : AsRef<Path>, W : Write {
+    let files = load(fname.as_ref())?;
+    let time = |t : Option<SystemTime>| t.map(|t| humantime::format_rfc3339_nanos(t).to_string());
+    let export = Export {
+        version : super::VERSION,
+        algorithm : super::HASH_ALGORITHM,
+        entries : files.iter().map(|hf| ExportEntry {
+            path : pathenc::escape(hf.path()),
+            hash : hex::encode(hf.hash()),
+            size : hf.size(),
+            modified : time(hf.modified()),
+            hashed_at : time(hf.hashed_at()),
+            file_id : hf.stat().file_id,
+            ctime : hf.stat().ctime,
+        }).collect(),
+    };
+    serde_json::to_writer_pretty(out, &export)?;
+    Ok(())
+}
+
+/// Remove the digests stored in extended attributes from the files in the
+/// folders, returns the number of files that had some
+#[cfg(feature = "xattr")]
+pub fn strip_xattr<P>(folders : &[P]) -> Result<u64> where P : AsRef<Path> {
+    let mut stripped = 0;
+    for folder in folders {
+        for entry in walkdir::WalkDir::new(folder) {
+            let entry = entry?;
+            if entry.file_type().is_file() {
+                let found = super::xattr::strip(entry.path())
+                    .map_err(|e| anyhow::format_err!("could not strip {}: {}",entry.path().display(),e))?;
+                stripped += found as u64;
+            }
+        }
+    }
+    Ok(stripped)
+}
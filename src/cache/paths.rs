@@ -0,0 +1,221 @@
+use std::borrow::Cow;
+use std::path::{Component,Path};
+
+use crate::types::{PathData,Result};
+use crate::pathenc;
+
+/// How the paths of the entries are stored in the cache file and read back.
+/// By default they are stored as found by the scan (so relative paths
+/// depend on the working directory). With a root, the paths under it are
+/// stored relative to it and the other ones as absolute paths, and the
+/// root is recorded in the cache: the cache keeps working when the root
+/// moves (another mount point, another host) as long as the new location
+/// is given. Remaps replace path prefixes when the cache is read.
+#[derive(Debug,Clone,Default)]
+pub struct PathMap {
+    /// absolute
+    root : Option<PathData>,
+    remaps : Vec<(PathData, PathData)>,
+    /// as given and absolute
+    scan_roots : Vec<(PathData, PathData)>,
+}
+
+impl PathMap {
+    /// Store the paths relative to this folder. When not set, the root
+    /// recorded in the cache (if any) is used when the cache is read.
+    pub fn set_root(&mut self, root : Option<PathData>) {
+        self.root = root.as_deref().map(absolute);
+    }
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+    /// Replace the first matching prefix of the cached paths when reading
+    pub fn add_remap(&mut self, old : PathData, new : PathData) {
+        self.remaps.push((old, new));
+    }
+    /// The folders as given to the scan: the cached paths found under
+    /// them are read back in the same form as the scan finds them
+    pub fn set_scan_roots(&mut self, roots : Vec<PathData>) {
+        self.scan_roots = roots.into_iter().map(|root| {
+            let abs = absolute(&root);
+            (root, abs)
+        }).collect();
+    }
+    /// Parse an OLD=NEW remap (the paths may be escaped the way the outputs write them)
+    pub fn parse_remap(s : &str) -> Result<(PathData, PathData)> {
+        match s.split_once('=') {
+            Some((old, new)) if !old.is_empty() && !new.is_empty() => Ok((pathenc::unescape(old)?, pathenc::unescape(new)?)),
+            _ => Err(anyhow::format_err!("invalid remap: {} (expected OLD=NEW)",s)),
+        }
+    }
+    /// The path to store in a cache file recording the root (if any)
+    pub(crate) fn store<'a>(&self, path : &'a Path) -> Cow<'a, Path> {
+        let Some(root) = &self.root else {
+            return Cow::Borrowed(path);
+        };
+        let path = absolute(path);
+        match path.strip_prefix(root) {
+            Ok(relative) => Cow::Owned(relative.to_owned()),
+            Err(_) => Cow::Owned(path),
+        }
+    }
+    /// The forms a path found by the scan can have in a cache that is not
+    /// loaded as a whole (see store::CacheStore): the stored form first,
+    /// then the ones the remaps would map to it
+    #[cfg(feature = "redb")]
+    pub(crate) fn keys(&self, path : &Path, relative : bool) -> Vec<PathData> {
+        let stored = |path : &Path| if relative { self.store(path).into_owned() } else { path.to_owned() };
+        let mut keys = vec![stored(path)];
+        let abs = absolute(path);
+        for (old, new) in &self.remaps {
+            let rest = path.strip_prefix(new).or_else(|_| abs.strip_prefix(new));
+            if let Ok(rest) = rest {
+                keys.push(stored(&old.join(rest)));
+            }
+        }
+        keys
+    }
+    /// Called when a cache file is read: without a root of its own, the
+    /// map takes the one recorded in the cache, so that the paths keep
+    /// being stored relative to it when the cache is written back
+    pub(crate) fn adopt_root(&mut self, recorded : Option<PathData>) {
+        if self.root.is_none() {
+            self.root = recorded;
+        }
+    }
+    /// The path found in a cache file, as the scan would find it.
+    /// relative tells if the cache stores paths relative to a root.
+    pub(crate) fn restore(&self, path : PathData, relative : bool) -> PathData {
+        let mut path = match &self.root {
+            Some(root) if relative && path.is_relative() => root.join(path),
+            _ => path,
+        };
+        if let Some((old, new)) = self.remaps.iter().find(|(old, _)| path.starts_with(old)) {
+            path = new.join(path.strip_prefix(old).unwrap_or(&path));
+        }
+        if !path.is_absolute() {
+            return path;
+        }
+        self.scan_roots.iter()
+            .filter(|(_, abs)| path.starts_with(abs))
+            .max_by_key(|(_, abs)| abs.components().count())
+            .map(|(root, abs)| root.join(path.strip_prefix(abs).unwrap_or(&path)))
+            .unwrap_or(path)
+    }
+}
+
+/// Absolute form of the path, without "." and ".." (the links are not resolved)
+pub(super) fn absolute(path : &Path) -> PathData {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let mut clean = PathData::new();
+    for c in path.components() {
+        match c {
+            Component::CurDir => {},
+            Component::ParentDir => { clean.pop(); },
+            c => clean.push(c),
+        }
+    }
+    clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(root : Option<&str>) -> PathMap {
+        let mut map = PathMap::default();
+        map.set_root(root.map(PathData::from));
+        map
+    }
+
+    #[test]
+    fn store_without_root_keeps_the_paths() {
+        let map = map(None);
+        assert_eq!(map.store(Path::new("some/relative")), Path::new("some/relative"));
+        assert_eq!(map.store(Path::new("/mnt/disk/file")), Path::new("/mnt/disk/file"));
+    }
+
+    #[test]
+    fn store_relative_to_the_root() {
+        let map = map(Some("/mnt/disk/../disk/"));
+        assert_eq!(map.root(), Some(Path::new("/mnt/disk")));
+        assert_eq!(map.store(Path::new("/mnt/disk/a/file")), Path::new("a/file"));
+        assert_eq!(map.store(Path::new("/mnt/disk/./a/../b")), Path::new("b"));
+        assert_eq!(map.store(Path::new("/mnt/other/file")), Path::new("/mnt/other/file"));
+        assert_eq!(map.store(Path::new("/mnt/diskette")), Path::new("/mnt/diskette"));
+    }
+
+    #[test]
+    fn restore_under_the_root() {
+        let map = map(Some("/media/usb"));
+        assert_eq!(map.restore(PathData::from("a/file"), true), Path::new("/media/usb/a/file"));
+        assert_eq!(map.restore(PathData::from("/mnt/other/file"), true), Path::new("/mnt/other/file"));
+        // a cache without a recorded root stores the paths as the scan found them
+        assert_eq!(map.restore(PathData::from("a/file"), false), Path::new("a/file"));
+    }
+
+    #[test]
+    fn store_then_restore_after_a_move() {
+        let old = map(Some("/mnt/disk"));
+        let stored = old.store(Path::new("/mnt/disk/photos/1.jpg")).into_owned();
+        let new = map(Some("/media/disk"));
+        assert_eq!(new.restore(stored, true), Path::new("/media/disk/photos/1.jpg"));
+    }
+
+    #[test]
+    fn adopts_the_recorded_root_only_without_one() {
+        let mut map = map(None);
+        map.adopt_root(Some(PathData::from("/recorded")));
+        assert_eq!(map.root(), Some(Path::new("/recorded")));
+        map.adopt_root(Some(PathData::from("/other")));
+        assert_eq!(map.root(), Some(Path::new("/recorded")));
+        map.adopt_root(None);
+        assert_eq!(map.root(), Some(Path::new("/recorded")));
+    }
+
+    #[test]
+    fn remap() {
+        let mut map = map(None);
+        map.add_remap(PathData::from("/old/share"), PathData::from("/new/share"));
+        map.add_remap(PathData::from("/old"), PathData::from("/elsewhere"));
+        assert_eq!(map.restore(PathData::from("/old/share/file"), false), Path::new("/new/share/file"));
+        assert_eq!(map.restore(PathData::from("/old/file"), false), Path::new("/elsewhere/file"));
+        assert_eq!(map.restore(PathData::from("/older/file"), false), Path::new("/older/file"));
+        assert_eq!(map.restore(PathData::from("/untouched"), false), Path::new("/untouched"));
+    }
+
+    #[test]
+    fn remap_after_the_root() {
+        let mut map = map(Some("/old/share"));
+        map.add_remap(PathData::from("/old/share"), PathData::from("/new/share"));
+        assert_eq!(map.restore(PathData::from("dir/file"), true), Path::new("/new/share/dir/file"));
+    }
+
+    #[test]
+    fn restore_in_the_form_of_the_scan_roots() {
+        let mut map = map(Some("/data"));
+        let cwd = std::env::current_dir().unwrap();
+        map.set_scan_roots(vec![PathData::from("/data"), PathData::from("relative")]);
+        assert_eq!(map.restore(PathData::from("file"), true), Path::new("/data/file"));
+        let under_relative = cwd.join("relative/file");
+        assert_eq!(map.restore(under_relative, false), Path::new("relative/file"));
+        // the deepest root wins
+        map.set_scan_roots(vec![PathData::from("/data"), PathData::from("/data/../data/sub")]);
+        assert_eq!(map.restore(PathData::from("sub/file"), true), Path::new("/data/../data/sub/file"));
+    }
+
+    #[test]
+    fn parse_remap() {
+        assert_eq!(PathMap::parse_remap("/old=/new").unwrap(), (PathData::from("/old"), PathData::from("/new")));
+        assert_eq!(PathMap::parse_remap("$'/a\\nb'=/new").unwrap(), (PathData::from("/a\nb"), PathData::from("/new")));
+        assert!(PathMap::parse_remap("/old").is_err());
+        assert!(PathMap::parse_remap("=/new").is_err());
+        assert!(PathMap::parse_remap("/old=").is_err());
+    }
+
+    #[test]
+    fn absolute_is_lexical() {
+        assert_eq!(absolute(Path::new("/a/./b/../c")), Path::new("/a/c"));
+        assert_eq!(absolute(Path::new("rel")), std::env::current_dir().unwrap().join("rel"));
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::{Duration,SystemTime};
+use serde::Serialize;
+
+use crate::hashedfile::HashedFile;
+
+/// Which cache entries are dropped before the cache is written
+/// (by default none are, so the cache keeps growing)
+#[derive(Debug,Clone,Default)]
+pub struct PrunePolicy {
+    /// entries under the scanned roots for files that were not found during
+    /// the run (deleted or renamed, the files skipped by the modification
+    /// time filter count as found)
+    pub unseen : bool,
+    /// entries for files that no longer exist, wherever they are
+    pub missing : bool,
+    /// entries hashed longer ago than this (entries from caches written
+    /// before the hashing time was recorded count as expired)
+    pub max_age : Option<Duration>,
+}
+
+/// Why a cache entry was dropped
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum PruneReason {
+    Unseen,
+    Missing,
+    Expired,
+}
+
+/// Number of cache entries kept and dropped, for each reason
+#[derive(Debug,Clone,Copy,Default,Serialize)]
+pub struct PruneStats {
+    pub kept : u64,
+    pub unseen : u64,
+    pub missing : u64,
+    pub expired : u64,
+}
+
+impl PrunePolicy {
+    pub fn is_active(&self) -> bool {
+        self.unseen || self.missing || self.max_age.is_some()
+    }
+    /// The reason to drop the entry, if any. seen tells if the file was
+    /// found during the run, under_roots if it is inside the scanned roots.
+    pub fn check(&self, hf : &HashedFile, seen : bool, under_roots : bool, now : SystemTime) -> Option<PruneReason> {
+        if self.unseen && under_roots && !seen {
+            return Some(PruneReason::Unseen);
+        }
+        if let Some(max_age) = self.max_age {
+            let expired = hf.hashed_at()
+                .is_none_or(|at| now.duration_since(at).is_ok_and(|age| age > max_age));
+            if expired {
+                return Some(PruneReason::Expired);
+            }
+        }
+        // checked last, it's the only one that touches the file system
+        if self.missing && !seen && std::fs::symlink_metadata(hf.path()).is_err() {
+            return Some(PruneReason::Missing);
+        }
+        None
+    }
+}
+
+impl PruneStats {
+    pub fn pruned(&self) -> u64 {
+        self.unseen + self.missing + self.expired
+    }
+    /// Count an entry as kept (None) or dropped for the reason
+    pub fn add(&mut self, reason : Option<PruneReason>) {
+        match reason {
+            None => self.kept += 1,
+            Some(PruneReason::Unseen) => self.unseen += 1,
+            Some(PruneReason::Missing) => self.missing += 1,
+            Some(PruneReason::Expired) => self.expired += 1,
+        }
+    }
+}
+
+impl std::fmt::Display for PruneStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} cache entries pruned (unseen: {}, missing: {}, expired: {}), {} kept",
+            self.pruned(), self.unseen, self.missing, self.expired, self.kept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashedfile::FileStat;
+    use crate::types::PathData;
+
+    const DAY : Duration = Duration::from_secs(24 * 3600);
+
+    fn file(path : &str, hashed_at : Option<SystemTime>) -> HashedFile {
+        HashedFile::from_parts(PathData::from(path), vec![0; 64], FileStat::default(), hashed_at)
+    }
+
+    #[test]
+    fn inactive_by_default() {
+        let policy = PrunePolicy::default();
+        assert!(!policy.is_active());
+        assert_eq!(policy.check(&file("/nowhere/at/all", None), false, true, SystemTime::now()), None);
+    }
+
+    #[test]
+    fn unseen() {
+        let policy = PrunePolicy { unseen : true, ..Default::default() };
+        let hf = file("/scanned/gone", None);
+        let now = SystemTime::now();
+        assert_eq!(policy.check(&hf, false, true, now), Some(PruneReason::Unseen));
+        assert_eq!(policy.check(&hf, true, true, now), None);
+        // outside of the scanned roots, nothing tells it's gone
+        assert_eq!(policy.check(&hf, false, false, now), None);
+    }
+
+    #[test]
+    fn max_age() {
+        let now = SystemTime::now();
+        let policy = PrunePolicy { max_age : Some(30 * DAY), ..Default::default() };
+        assert_eq!(policy.check(&file("/f", Some(now - 31 * DAY)), true, true, now), Some(PruneReason::Expired));
+        assert_eq!(policy.check(&file("/f", Some(now - 29 * DAY)), true, true, now), None);
+        // hashed "in the future" (clock changes) is not expired
+        assert_eq!(policy.check(&file("/f", Some(now + DAY)), true, true, now), None);
+        // written before the hashing time was recorded
+        assert_eq!(policy.check(&file("/f", None), true, true, now), Some(PruneReason::Expired));
+    }
+
+    #[test]
+    fn missing() {
+        let now = SystemTime::now();
+        let policy = PrunePolicy { missing : true, ..Default::default() };
+        let gone = file("/this/file/does/not/exist", None);
+        assert_eq!(policy.check(&gone, false, false, now), Some(PruneReason::Missing));
+        assert_eq!(policy.check(&gone, true, false, now), None);
+        let here = file(env!("CARGO_MANIFEST_DIR"), None);
+        assert_eq!(policy.check(&here, false, false, now), None);
+    }
+
+    #[test]
+    fn unseen_comes_first() {
+        let now = SystemTime::now();
+        let policy = PrunePolicy { unseen : true, missing : true, max_age : Some(DAY) };
+        let hf = file("/this/file/does/not/exist", None);
+        assert_eq!(policy.check(&hf, false, true, now), Some(PruneReason::Unseen));
+        assert_eq!(policy.check(&hf, false, false, now), Some(PruneReason::Expired));
+    }
+
+    #[test]
+    fn stats() {
+        let mut stats = PruneStats::default();
+        for reason in [None, None, Some(PruneReason::Unseen), Some(PruneReason::Missing), Some(PruneReason::Expired), Some(PruneReason::Expired)] {
+            stats.add(reason);
+        }
+        assert_eq!((stats.kept, stats.unseen, stats.missing, stats.expired, stats.pruned()), (2, 1, 1, 2, 4));
+        assert_eq!(stats.to_string(), "4 cache entries pruned (unseen: 1, missing: 1, expired: 2), 2 kept");
+    }
+}
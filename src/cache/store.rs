@@ -0,0 +1,187 @@
+use std::borrow::Cow;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration,Instant};
+use anyhow::Context;
+use redb::{Database,DatabaseError,TableDefinition,ReadableTable};
+use bincode::Options;
+
+use crate::types::{PathData,Result};
+use crate::hashedfile::HashedFile;
+use crate::pathenc;
+use super::{PathMap,Entry,HASH_ALGORITHM,HINT,to_entry,from_entry,path_from,bounded};
+
+/// Stored path -> bincode Entry (without the path)
+const ENTRIES : TableDefinition<&[u8], &[u8]> = TableDefinition::new("entries");
+/// Format version, hash algorithm and root (if any) of the store
+const META : TableDefinition<&str, &[u8]> = TableDefinition::new("meta");
+/// First bytes of a redb database
+const REDB_MAGIC : &[u8; 9] = b"redb\x1a\x0a\xa9\x0d\x0a";
+/// Format of the values and meta data written by this version
+const STORE_VERSION : u32 = 1;
+/// How long to wait for another process to close the store
+const OPEN_TIMEOUT : Duration = Duration::from_secs(60);
+
+/// Cache kept in an embedded database (redb) instead of a file rewritten
+/// as a whole: the entries are looked up by path while scanning instead
+/// of being loaded beforehand, and only the entries that changed are
+/// written after the run. The root (see PathMap) is recorded when the
+/// store is created and kept afterwards. A single process can have the
+/// store open: the others wait for it to be closed (see open).
+pub struct CacheStore {
+    db : Database,
+    /// the paths are stored relative to the recorded root
+    relative : bool,
+}
+
+/// Tell if the cache file is a store: an existing redb database, or a
+/// file to be created with the .redb extension
+pub fn is_store(fname : &Path) -> bool {
+    match std::fs::File::open(fname) {
+        Ok(mut file) => {
+            let mut magic = [0; 9];
+            file.read_exact(&mut magic).is_ok() && magic == *REDB_MAGIC
+        },
+        Err(_) => fname.extension().is_some_and(|ext| ext == "redb"),
+    }
+}
+
+impl CacheStore {
+    /// Open the store, creating it if needed. The map takes the root
+    /// recorded in the store if it has none (see PathMap::adopt_root).
+    /// While another process has it open, wait up to OPEN_TIMEOUT.
+    pub fn open(fname : &Path, map : &mut PathMap) -> Result<Self> {
+        Self::try_open(fname, map).with_context(|| format!("could not open cache store {}", fname.display()))
+    }
+    fn try_open(fname : &Path, map : &mut PathMap) -> Result<Self> {
+        let db = create(fname)?;
+        let txn = db.begin_write()?;
+        let root = {
+            let mut meta = txn.open_table(META)?;
+            let version = meta.get("version")?.map(|v| v.value().to_vec());
+            match version {
+                None => {
+                    meta.insert("version", STORE_VERSION.to_le_bytes().as_slice())?;
+                    meta.insert("algorithm", HASH_ALGORITHM.as_bytes())?;
+                    if let Some(root) = map.root() {
+                        meta.insert("root", pathenc::to_bytes(root))?;
+                    }
+                },
+                Some(version) => {
+                    let version = version.try_into().map(u32::from_le_bytes).unwrap_or(u32::MAX);
+                    if version != STORE_VERSION {
+                        return Err(anyhow::format_err!("cache store version {} is not supported by this fdedup ({}), {}", version, STORE_VERSION, HINT));
+                    }
+                    let algorithm = meta.get("algorithm")?.map(|a| a.value().to_vec()).unwrap_or_default();
+                    if algorithm != HASH_ALGORITHM.as_bytes() {
+                        return Err(anyhow::format_err!("the cache holds {} digests but this fdedup uses {}, {}", String::from_utf8_lossy(&algorithm), HASH_ALGORITHM, HINT));
+                    }
+                },
+            }
+            let root = meta.get("root")?.map(|r| r.value().to_vec());
+            root.map(path_from).transpose()?
+        };
+        txn.open_table(ENTRIES)?;
+        txn.commit()?;
+        let relative = root.is_some();
+        map.adopt_root(root);
+        Ok(Self { db, relative })
+    }
+    /// The entry of a file found by the scan (tried under each form the
+    /// map gives for its path)
+    pub fn get(&self, path : &Path, map : &PathMap) -> Result<Option<HashedFile>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(ENTRIES)?;
+        for key in map.keys(path, self.relative) {
+            if let Some(value) = table.get(pathenc::to_bytes(&key))? {
+                return Ok(Some(decode(path.to_owned(), value.value())?));
+            }
+        }
+        Ok(None)
+    }
+    /// All the entries, with their paths as the scan would find them
+    pub fn entries(&self, map : &PathMap) -> Result<Vec<HashedFile>> {
+        let txn = self.db.begin_read()?;
+        let table = txn.open_table(ENTRIES)?;
+        table.iter()?.map(|item| {
+            let (key, value) = item?;
+            let path = map.restore(path_from(key.value().to_vec())?, self.relative);
+            decode(path, value.value())
+        }).collect()
+    }
+    /// Write the entries that differ from the stored ones, returns their number
+    pub fn update<'a,I>(&self, files : I, map : &PathMap) -> Result<u64> where I : IntoIterator<Item = &'a HashedFile> {
+        let txn = self.db.begin_write()?;
+        let mut written = 0;
+        {
+            let mut table = txn.open_table(ENTRIES)?;
+            for hf in files {
+                let key = self.key(hf.path(), map);
+                let value = bincode::serialize(&to_entry(hf, vec![]))?;
+                let unchanged = table.get(pathenc::to_bytes(&key))?.is_some_and(|v| v.value() == value.as_slice());
+                if !unchanged {
+                    table.insert(pathenc::to_bytes(&key), value.as_slice())?;
+                    written += 1;
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(written)
+    }
+    /// Drop the entries rejected by the closure (their paths as the scan
+    /// would find them), along with the ones that can't be decoded
+    pub fn prune<F>(&self, map : &PathMap, mut reject : F) -> Result<()> where F : FnMut(&HashedFile) -> bool {
+        let txn = self.db.begin_write()?;
+        txn.open_table(ENTRIES)?.retain(|key, value| {
+            let hf = path_from(key.to_vec()).and_then(|path| decode(map.restore(path, self.relative), value));
+            hf.is_ok_and(|hf| !reject(&hf))
+        })?;
+        txn.commit()?;
+        Ok(())
+    }
+    /// Replace all the entries, then give the freed space back. Only for
+    /// the entries read from the store (see cache::read), the store holds
+    /// the ones of the other processes too.
+    pub fn replace<'a,I>(mut self, files : I, map : &PathMap) -> Result<()> where I : IntoIterator<Item = &'a HashedFile> {
+        let txn = self.db.begin_write()?;
+        {
+            let mut table = txn.open_table(ENTRIES)?;
+            table.retain(|_, _| false)?;
+            for hf in files {
+                let value = bincode::serialize(&to_entry(hf, vec![]))?;
+                table.insert(pathenc::to_bytes(&self.key(hf.path(), map)), value.as_slice())?;
+            }
+        }
+        txn.commit()?;
+        self.db.compact()?;
+        Ok(())
+    }
+    fn key<'a>(&self, path : &'a Path, map : &PathMap) -> Cow<'a, Path> {
+        if self.relative { map.store(path) } else { Cow::Borrowed(path) }
+    }
+}
+
+/// redb locks the database for the process that opened it until it is closed
+fn create(fname : &Path) -> Result<Database> {
+    let start = Instant::now();
+    let mut warned = false;
+    loop {
+        match Database::create(fname) {
+            Err(DatabaseError::DatabaseAlreadyOpen) if start.elapsed() < OPEN_TIMEOUT => {
+                if !warned {
+                    eprintln!("Warning: the cache store {} is open in another fdedup, waiting up to {}s for it", fname.display(), OPEN_TIMEOUT.as_secs());
+                    warned = true;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            },
+            Err(DatabaseError::DatabaseAlreadyOpen) => return Err(anyhow::format_err!("still open in another fdedup after {}s", OPEN_TIMEOUT.as_secs())),
+            result => return Ok(result?),
+        }
+    }
+}
+
+fn decode(path : PathData, value : &[u8]) -> Result<HashedFile> {
+    let entry : Entry = bounded(value.len()).reject_trailing_bytes().deserialize(value)
+        .map_err(|e| anyhow::format_err!("corrupted cache store entry for {} ({})", path.display(), e))?;
+    Ok(from_entry(entry)?.with_path(path))
+}
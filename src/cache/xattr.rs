@@ -0,0 +1,132 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::time::{Duration,SystemTime,UNIX_EPOCH};
+
+use crate::types::Result;
+use crate::verbose::vprintln;
+use crate::hashedfile::{HashedFile,FileStat};
+use super::{CacheCheck,HASH_ALGORITHM};
+
+/// Prefix of the extended attributes written on the files
+pub const PREFIX : &str = "user.fdedup.";
+const HASH : &str = "user.fdedup.hash";
+const ALGORITHM : &str = "user.fdedup.algorithm";
+const SIZE : &str = "user.fdedup.size";
+const MTIME : &str = "user.fdedup.mtime";
+const HASHED_AT : &str = "user.fdedup.hashed_at";
+
+/// Set once the user was told that attributes could not be written
+static WARNED : AtomicBool = AtomicBool::new(false);
+
+/// The digest recorded in the extended attributes of the file, if it still
+/// applies: same algorithm, size and modification time. The device, inode
+/// and ctime are not recorded since copies get new ones (and writing the
+/// attributes changes the ctime), so the attributes can't pass the strict
+/// check: they are only used with the fast one.
+pub fn load(path : &Path, stat : &FileStat, check : CacheCheck) -> Option<HashedFile> {
+    if check != CacheCheck::Fast {
+        return None;
+    }
+    let get = |name| ::xattr::get(path, name).ok().flatten();
+    let hash = get(HASH)?;
+    if get(ALGORITHM)? != HASH_ALGORITHM.as_bytes() {
+        vprintln!(2,"ignoring the digest in the attributes of {} (other algorithm)",path.display());
+        return None;
+    }
+    let size = parse(&get(SIZE)?)?;
+    let modified = get(MTIME).and_then(|t| time(&t));
+    if size != stat.size || modified.is_none() || modified != stat.modified {
+        vprintln!(2,"ignoring the digest in the attributes of {} (file changed)",path.display());
+        return None;
+    }
+    let hashed_at = get(HASHED_AT).and_then(|t| time(&t));
+    Some(HashedFile::from_parts(path.to_owned(), hash, *stat, hashed_at))
+}
+
+/// Record the digest of the file in its extended attributes. Returns the
+/// entry with the ctime changed by the writing. Files that can't get
+/// attributes (file system without them, read-only...) keep their digest
+/// in the cache file only.
+pub fn store(hf : HashedFile) -> HashedFile {
+    let Some(modified) = hf.modified() else {
+        return hf;
+    };
+    let result = write(&hf, modified).and_then(|_| std::fs::metadata(hf.path()));
+    match result {
+        Ok(meta) => {
+            let stat = FileStat { ctime : FileStat::from_metadata(&meta).ctime, ..*hf.stat() };
+            hf.with_stat(stat)
+        },
+        Err(e) => {
+            if !WARNED.swap(true, Ordering::Relaxed) {
+                eprintln!("Warning: could not store the digest of {} in its extended attributes ({}), the digests that can't be stored are only kept in the cache file",hf.path().display(),e);
+            }
+            vprintln!(2,"could not store the digest in the attributes of {}: {}",hf.path().display(),e);
+            hf
+        },
+    }
+}
+
+fn write(hf : &HashedFile, modified : SystemTime) -> std::io::Result<()> {
+    let path = hf.path();
+    // the old digest goes first and the new one last so that a partial
+    // write never leaves a digest next to metadata it doesn't match
+    if ::xattr::get(path, HASH)?.is_some() {
+        ::xattr::remove(path, HASH)?;
+    }
+    ::xattr::set(path, ALGORITHM, HASH_ALGORITHM.as_bytes())?;
+    ::xattr::set(path, SIZE, hf.size().to_string().as_bytes())?;
+    ::xattr::set(path, MTIME, format_time(modified).as_bytes())?;
+    if let Some(hashed_at) = hf.hashed_at() {
+        ::xattr::set(path, HASHED_AT, format_time(hashed_at).as_bytes())?;
+    }
+    ::xattr::set(path, HASH, hf.hash())
+}
+
+/// Remove the attributes written by store from a file, returns true if it had some
+pub fn strip(path : &Path) -> Result<bool> {
+    let names : Vec<_> = ::xattr::list(path)?
+        .filter(|name| name.to_str().is_some_and(|n| n.starts_with(PREFIX)))
+        .collect();
+    // the digest goes first so that a partial removal never leaves a usable one
+    let (hash, others) : (Vec<_>, Vec<_>) = names.iter().partition(|name| *name == HASH);
+    for name in hash.into_iter().chain(others) {
+        ::xattr::remove(path, name)?;
+    }
+    Ok(!names.is_empty())
+}
+
+/// Time as seconds.nanoseconds since the epoch
+fn format_time(t : SystemTime) -> String {
+    let d = t.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}",d.as_secs(),d.subsec_nanos())
+}
+
+fn time(bytes : &[u8]) -> Option<SystemTime> {
+    let (secs, nanos) = std::str::from_utf8(bytes).ok()?.split_once('.')?;
+    UNIX_EPOCH.checked_add(Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+fn parse(bytes : &[u8]) -> Option<u64> {
+    std::str::from_utf8(bytes).ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reused_with_the_fast_check() {
+        let fname = std::env::temp_dir().join(format!("fdedup-xattr-load-{}", std::process::id()));
+        std::fs::write(&fname, b"content").unwrap();
+        let stat = FileStat::from_metadata(&std::fs::metadata(&fname).unwrap());
+        let stored = store(HashedFile::new(fname.clone(), stat).unwrap());
+        // the file system of the temporary folder may not have extended attributes
+        if ::xattr::get(&fname, HASH).unwrap().is_some() {
+            assert_eq!(load(&fname, stored.stat(), CacheCheck::Fast).map(|hf| hf.hash().clone()), Some(stored.hash().clone()));
+            assert!(load(&fname, stored.stat(), CacheCheck::Strict).is_none());
+            assert!(load(&fname, stored.stat(), CacheCheck::Paranoid).is_none());
+        }
+        std::fs::remove_file(&fname).unwrap();
+    }
+}
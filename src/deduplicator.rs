@@ -1,22 +1,71 @@
 use crate::types::{Result, PathData};
 use crate::dedupstate::DedupState;
-use crate::duplicates::Duplicates;
-use crate::hashedfile::HashedFile;
+use crate::duplicates::{Duplicates,FileId};
+use crate::hashedfile::{HashedFile,FileStat};
+use crate::cache::{self,location::Artifacts,CacheCheck,Compression,CacheLookup,CacheLock,PathMap,PrunePolicy,PruneStats};
+use crate::filter::{MtimeFilter,MtimeCheck};
+use crate::stats::{self,Summary,Invalidations};
+use crate::event::{Monitor,EventHandler,Progress};
+use crate::sort::{self,SortOrder,GroupKey,PathKey};
+use crate::verbose::vprintln;
+use std::time::{SystemTime,Duration,Instant};
+use std::path::Path;
+
+mod builder;
+pub use self::builder::{DeduplicatorBuilder,BuildError};
+
+#[derive(Debug,Clone)]
+struct Root {
+    path : PathData,
+    reference : bool,
+}
 
 pub struct Deduplicator {
-    dirs : Vec<PathData>,
+    roots : Vec<Root>,
     dedup_state : DedupState,
     normalize_path : bool,
     threads : Option<usize>,
+    mtime_filter : MtimeFilter,
+    monitor : Monitor,
+    group_order : SortOrder<GroupKey>,
+    path_order : SortOrder<PathKey>,
+    cache_check : CacheCheck,
+    prune_policy : PrunePolicy,
+    pruned : PruneStats,
+    path_map : PathMap,
+    /// never scanned
+    artifacts : Artifacts,
+    /// None keeps the compression of the cache file
+    cache_compression : Option<Compression>,
+    /// the cache file of load_cache and save_cache (see DeduplicatorBuilder)
+    cache_file : Option<PathData>,
+    /// load_cache starts with an empty cache
+    empty_cache : bool,
+    #[cfg(feature = "xattr")]
+    xattr : bool,
+    /// the cache when it is a store, looked up while scanning instead of being loaded
+    #[cfg(feature = "redb")]
+    store : Option<cache::store::CacheStore>,
+    /// metadata of the cache file when it was read (None if read_cache was
+    /// not called, Some(None) if there was no cache file), to tell if another
+    /// process replaced it before write_cache
+    cache_stamp : Option<Option<FileStat>>,
 }
 
 impl Deduplicator {
+    /// Configure a deduplicator, with validation (see DeduplicatorBuilder)
+    pub fn builder() -> DeduplicatorBuilder {
+        DeduplicatorBuilder::default()
+    }
     pub fn new<S,D>(dirs : D) -> Self where S : Into<PathData>, D: Into<Vec<S>> {
         Self {
-            dirs : dirs.into().into_iter().map(|d|d.into()).collect(),
+            roots : dirs.into().into_iter().map(|d| Root { path : d.into(), reference : false }).collect(),
             ..Default::default()
         }
     }
+    /// Number of hashing threads, None for the number of cores. Some(0) is
+    /// ignored, the number of threads stays as it was (the number of cores
+    /// unless set before); DeduplicatorBuilder::build rejects it.
     pub fn set_threads(&mut self, threads : Option<usize>) {
         if let Some(0) = threads {
             return;
@@ -24,20 +73,224 @@ impl Deduplicator {
         self.threads = threads;
     }
     pub fn add_dir<S>(&mut self, dir: S) where S : Into<PathData> {
-        self.dirs.push(dir.into());
+        self.add_root(dir.into(), false);
+    }
+    /// Add a read-only reference folder: its files are matched against
+    /// but a group is only reported if it has at least one file outside
+    /// of the reference folders, and reference files are never candidates
+    /// for removal (see Duplicates::survivor and Duplicates::non_survivors)
+    pub fn add_reference_dir<S>(&mut self, dir: S) where S : Into<PathData> {
+        self.add_root(dir.into(), true);
+    }
+    fn add_root(&mut self, path : PathData, reference : bool) {
+        let lpath = lexical(&path);
+        if let Some(root) = self.roots.iter_mut().find(|r| lexical(&r.path) == lpath) {
+            // same folder given twice: being a reference wins
+            root.reference |= reference;
+        } else {
+            self.roots.push(Root { path, reference });
+        }
     }
     pub fn set_normalize_path(&mut self, normalize : bool) {
         self.normalize_path = normalize;
     }
+    /// Only scan files with a modification time accepted by the filter
+    pub fn set_mtime_filter(&mut self, filter : MtimeFilter) {
+        self.mtime_filter = filter;
+    }
+    pub fn mtime_filter(&self) -> &MtimeFilter {
+        &self.mtime_filter
+    }
+    /// Order of the groups returned by run (default is ascending size)
+    pub fn set_group_order(&mut self, order : SortOrder<GroupKey>) {
+        self.group_order = order;
+    }
+    /// Order of the paths inside each group (default is by name).
+    /// The first path is the survivor (a reference file if the group has one).
+    pub fn set_path_order(&mut self, order : SortOrder<PathKey>) {
+        self.path_order = order;
+    }
+    /// How cached digests are validated against the files (default is strict)
+    pub fn set_cache_check(&mut self, check : CacheCheck) {
+        self.cache_check = check;
+    }
+    /// Which cache entries write_cache drops (default is none)
+    pub fn set_prune_policy(&mut self, policy : PrunePolicy) {
+        self.monitor.record_filtered(policy.unseen);
+        self.prune_policy = policy;
+    }
+    /// Store the cached paths relative to this folder (see cache::PathMap)
+    pub fn set_cache_root(&mut self, root : Option<PathData>) {
+        self.path_map.set_root(root);
+    }
+    /// How write_cache compresses the cache file (default is to keep its
+    /// current compression, none for a new file). Stores are not compressed.
+    pub fn set_cache_compression(&mut self, compression : Option<Compression>) {
+        self.cache_compression = compression;
+    }
+    /// Replace the OLD prefix of the cached paths by NEW when reading the cache
+    pub fn add_cache_remap(&mut self, old : PathData, new : PathData) {
+        self.path_map.add_remap(old, new);
+    }
+    /// Never scan this file nor its lock and temporary files (for a cache
+    /// file not named like the ones always skipped, see cache::location::Artifacts)
+    pub fn exclude<P>(&mut self, path : P) where P : AsRef<std::path::Path> {
+        self.artifacts.add(path);
+    }
+    /// Also keep the digests in extended attributes of the files (see cache::xattr)
+    #[cfg(feature = "xattr")]
+    pub fn set_xattr(&mut self, xattr : bool) {
+        self.xattr = xattr;
+    }
+    /// Number of files skipped during the last run because they were
+    /// modified within the settle time
+    pub fn skipped_recent(&self) -> u64 {
+        stats::get(&self.monitor.counters.skipped_recent)
+    }
+    /// Number of files skipped during the last run because they were
+    /// outside of the --newer-than/--older-than window
+    pub fn skipped_out_of_range(&self) -> u64 {
+        stats::get(&self.monitor.counters.skipped_out_of_range)
+    }
+    /// Receive the scan events (start, progress, errors) while running.
+    /// With threads, the handler can be called from any of them.
+    pub fn set_event_handler(&mut self, handler : Option<EventHandler>) {
+        self.monitor.set_handler(handler);
+    }
+    /// Minimum time between two progress events (default is 1 second)
+    pub fn set_progress_interval(&mut self, interval : Duration) {
+        self.monitor.set_progress_interval(interval);
+    }
+    /// Counters of the current or last run
+    pub fn progress(&self) -> Progress {
+        self.monitor.progress()
+    }
+    /// Figures about the last run and the duplicates it found
+    pub fn summary(&self, duplicates : &[Duplicates]) -> Summary {
+        let counters = &self.monitor.counters;
+        let timings = self.monitor.timings.lock().map(|t| *t).unwrap_or_default();
+        let bytes_hashed = stats::get(&counters.bytes_hashed);
+        let scan = timings.scan.as_secs_f64();
+        Summary {
+            files_scanned : stats::get(&counters.files_seen),
+            files_hashed : stats::get(&counters.files_hashed),
+            cache_hits : stats::get(&counters.cache_hits),
+            cache_misses : stats::get(&counters.files_seen) - stats::get(&counters.cache_hits),
+            cache_invalidated : Invalidations::from_counters(&counters.invalidated),
+            cache_pruned : self.pruned,
+            errors : stats::get(&counters.errors),
+            skipped_recent : stats::get(&counters.skipped_recent),
+            skipped_out_of_range : stats::get(&counters.skipped_out_of_range),
+            bytes_hashed,
+            timings,
+            hash_rate : if scan > 0.0 { bytes_hashed as f64 / scan } else { 0.0 },
+            ..Summary::new(duplicates)
+        }
+    }
+    /// Load the cache file (a missing or unusable cache is reported and ignored).
+    /// A store (see cache::store) is only opened.
     pub fn read_cache<S>(&mut self, fname: S) where S: Into<PathData> {
         let fname = fname.into();
-        match self.dedup_state.read_cache(&fname) {
+        let start = Instant::now();
+        #[cfg(feature = "redb")]
+        if cache::store::is_store(&fname) {
+            self.path_map.set_scan_roots(self.roots.iter().map(|r| r.path.clone()).collect());
+            match cache::store::CacheStore::open(&fname, &mut self.path_map) {
+                Ok(store) => self.store = Some(store),
+                Err(e) => eprintln!("Warning: {:#}",e),
+            }
+            self.monitor.timed(|t| &mut t.cache_read, start.elapsed());
+            return;
+        }
+        let lock = CacheLock::acquire(&fname);
+        if let Err(_e) = &lock {
+            vprintln!(1,"reading the cache without locking it: {:#}",_e);
+        }
+        self.cache_stamp = Some(cache_stamp(&fname));
+        self.path_map.set_scan_roots(self.roots.iter().map(|r| r.path.clone()).collect());
+        match self.dedup_state.read_cache(&fname, &mut self.path_map) {
             Ok(_) => { }
-            _ => { println!("Warning: could not load cache file {}",fname.display()); }
+            // no cache yet: the first run with these folders
+            Err(_e) if self.cache_stamp == Some(None) => { vprintln!(1,"no cache file yet: {:#}",_e); }
+            Err(e) => { eprintln!("Warning: {:#}",e); }
+        }
+        drop(lock);
+        self.monitor.timed(|t| &mut t.cache_read, start.elapsed());
+    }
+    /// The cache file chosen by the builder (None when the cache is disabled)
+    pub fn cache_file(&self) -> Option<&Path> {
+        self.cache_file.as_deref()
+    }
+    /// Read the cache file chosen by the builder, unless it starts with an empty cache
+    pub fn load_cache(&mut self) {
+        if let (Some(fname), false) = (self.cache_file.clone(), self.empty_cache) {
+            self.read_cache(fname);
         }
     }
+    /// Write the cache file chosen by the builder (see write_cache)
+    pub fn save_cache(&mut self) -> Result<()> {
+        match self.cache_file.clone() {
+            Some(fname) => self.write_cache(fname),
+            None => Ok(()),
+        }
+    }
+    /// Write the cache, without the entries rejected by the prune policy.
+    /// If another process updated the cache file since read_cache, its
+    /// entries are merged in instead of being lost.
     pub fn write_cache<S>(&mut self, fname: S) -> Result<()> where S: Into<PathData> {
-        self.dedup_state.write_cache(fname.into())
+        let fname = fname.into();
+        let start = Instant::now();
+        #[cfg(feature = "redb")]
+        if cache::store::is_store(&fname) {
+            let result = self.write_store(&fname);
+            self.monitor.timed(|t| &mut t.cache_write, start.elapsed());
+            return result;
+        }
+        let _lock = CacheLock::acquire(&fname)?;
+        let stamp = cache_stamp(&fname);
+        if stamp.is_some() && self.cache_stamp.is_some_and(|read| read != stamp) {
+            self.path_map.set_scan_roots(self.roots.iter().map(|r| r.path.clone()).collect());
+            match cache::read(&fname, &mut self.path_map) {
+                Ok(entries) => {
+                    let _taken = self.dedup_state.merge_cache(entries);
+                    vprintln!(1,"cache file updated by another process, {} entries merged",_taken);
+                },
+                Err(e) => { eprintln!("Warning: {:#} (overwriting it)",e); }
+            }
+        }
+        // after the merge, so that the entries of the other process are pruned too
+        if self.prune_policy.is_active() {
+            self.prune()?;
+        }
+        let compression = self.cache_compression.unwrap_or_else(|| cache::compression_of(&fname));
+        let result = self.dedup_state.write_cache(&fname, &self.path_map, compression);
+        if result.is_ok() && self.cache_stamp.is_some() {
+            self.cache_stamp = Some(cache_stamp(&fname));
+        }
+        self.monitor.timed(|t| &mut t.cache_write, start.elapsed());
+        result
+    }
+    /// Write the entries of the run to the store. The store is only updated,
+    /// never replaced: when read_cache could not open it (another process
+    /// had it), it holds entries this run never saw. It is closed once
+    /// written so that the other processes can open it.
+    #[cfg(feature = "redb")]
+    fn write_store(&mut self, fname : &Path) -> Result<()> {
+        // the cache commands hold the lock between reading and replacing a store
+        let _lock = CacheLock::acquire(fname)?;
+        if self.store.is_none() {
+            self.path_map.set_scan_roots(self.roots.iter().map(|r| r.path.clone()).collect());
+            self.store = Some(cache::store::CacheStore::open(fname, &mut self.path_map)?);
+        }
+        if self.prune_policy.is_active() {
+            self.prune()?;
+        }
+        let Some(store) = self.store.take() else {
+            return Ok(());
+        };
+        let _written = store.update(self.dedup_state.cached_files().iter(), &self.path_map)?;
+        vprintln!(1,"{} cache store entries written",_written);
+        Ok(())
     }
     #[cfg(all(any(feature = "mutex", feature = "dashmap"),feature = "threads"))]
     pub fn run(&self) -> Result<Vec<Duplicates>> {
@@ -46,50 +299,75 @@ impl Deduplicator {
                 std::env::set_var("RAYON_NUM_THREADS", format!("{}",threads));
             }
         }
+        let start = self.start();
         rayon::scope(|s| {
-            for dir in &self.dirs {
-                let walk = walkdir::WalkDir::new(dir).into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.file_type().is_file());
-                for entry in walk {
-                    let mut path = entry.path().to_owned();
-                    if self.normalize_path {
-                        apply_path_normalization(&mut path);
-                    }
-                    let modified = entry.metadata().ok().and_then(|meta|meta.modified().ok());
-                    s.spawn(move |_| {
-                        if !self.dedup_state.reuse_if_cached(&path, &modified) {
-                            if let Ok(hf) = HashedFile::new(path,modified) {
+            for (path, stat) in walk(&self.roots, self.normalize_path, &self.mtime_filter, &self.artifacts, &self.monitor) {
+                s.spawn(move |_| {
+                    let lookup = self.dedup_state.reuse_if_cached(&path, &stat, self.cache_check);
+                    #[cfg(feature = "redb")]
+                    let lookup = reuse_stored(&self.store, &self.path_map, lookup, &path, &stat, self.cache_check, |hf| self.dedup_state.add_hashed_file(hf));
+                    match lookup {
+                        CacheLookup::Hit => self.monitor.cache_hit(),
+                        lookup => {
+                            #[cfg(feature = "xattr")]
+                            if let Some(hf) = self.xattr.then(|| cache::xattr::load(&path, &stat, self.cache_check)).flatten() {
+                                self.monitor.cache_hit();
                                 self.dedup_state.add_hashed_file(hf);
+                                return;
                             }
-                        }
-                    });
-                }
+                            let expected = self.monitor.cache_missed(&path, lookup);
+                            match HashedFile::new(path.clone(),stat) {
+                                Ok(hf) => {
+                                    #[cfg(feature = "xattr")]
+                                    let hf = if self.stores_xattr(hf.path()) { cache::xattr::store(hf) } else { hf };
+                                    self.monitor.hashed(hf.size());
+                                    self.monitor.verified(&hf, expected);
+                                    self.dedup_state.add_hashed_file(hf);
+                                },
+                                Err(e) => self.monitor.error(&path, &e),
+                            }
+                        },
+                    }
+                });
             }
         });
-        Ok(self.dedup_state.duplicates())
+        self.monitor.finish();
+        self.monitor.timed(|t| &mut t.scan, start.elapsed());
+        Ok(self.collect_duplicates())
     }
     #[cfg(not(any(feature = "channel", feature = "mutex", feature = "dashmap")))]
     pub fn run(&mut self) -> Result<Vec<Duplicates>> {
-        let state = &mut self.dedup_state;
-        for dir in &self.dirs {
-            let walk = walkdir::WalkDir::new(dir).into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.file_type().is_file());
-            for entry in walk {
-                let mut path = entry.path().to_owned();
-                if self.normalize_path {
-                    apply_path_normalization(&mut path);
-                }
-                let modified = entry.metadata().ok().and_then(|meta|meta.modified().ok());
-                if !state.reuse_if_cached(&path, &modified) {
-                    if let Ok(hf) = HashedFile::new(path,modified) {
-                        state.add_hashed_file(hf);
+        let start = self.start();
+        for (path, stat) in walk(&self.roots, self.normalize_path, &self.mtime_filter, &self.artifacts, &self.monitor) {
+            let lookup = self.dedup_state.reuse_if_cached(&path, &stat, self.cache_check);
+            #[cfg(feature = "redb")]
+            let lookup = reuse_stored(&self.store, &self.path_map, lookup, &path, &stat, self.cache_check, |hf| self.dedup_state.add_hashed_file(hf));
+            match lookup {
+                CacheLookup::Hit => self.monitor.cache_hit(),
+                lookup => {
+                    #[cfg(feature = "xattr")]
+                    if let Some(hf) = self.xattr.then(|| cache::xattr::load(&path, &stat, self.cache_check)).flatten() {
+                        self.monitor.cache_hit();
+                        self.dedup_state.add_hashed_file(hf);
+                        continue;
                     }
-                }
+                    let expected = self.monitor.cache_missed(&path, lookup);
+                    match HashedFile::new(path.clone(),stat) {
+                        Ok(hf) => {
+                            #[cfg(feature = "xattr")]
+                            let hf = if self.stores_xattr(hf.path()) { cache::xattr::store(hf) } else { hf };
+                            self.monitor.hashed(hf.size());
+                            self.monitor.verified(&hf, expected);
+                            self.dedup_state.add_hashed_file(hf);
+                        },
+                        Err(e) => self.monitor.error(&path, &e),
+                    }
+                },
             }
         }
-        Ok(self.dedup_state.duplicates())
+        self.monitor.finish();
+        self.monitor.timed(|t| &mut t.scan, start.elapsed());
+        Ok(self.collect_duplicates())
     }
     #[cfg(all(feature = "channel", feature = "threads"))]
     pub fn run(&mut self) -> Result<Vec<Duplicates>> {
@@ -102,49 +380,260 @@ impl Deduplicator {
             }
         }
         let pool = threadpool::ThreadPool::new(pool_threads);
-        for dir in &self.dirs {
-            let walk = walkdir::WalkDir::new(dir).into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.file_type().is_file());
-            for entry in walk {
-                let mut path = entry.path().to_owned();
-                if self.normalize_path {
-                    apply_path_normalization(&mut path);
-                }
-                let modified = entry.metadata().ok().and_then(|meta|meta.modified().ok());
-                if !self.dedup_state.reuse_if_cached(&path, &modified) {
+        let start = self.start();
+        for (path, stat) in walk(&self.roots, self.normalize_path, &self.mtime_filter, &self.artifacts, &self.monitor) {
+            let lookup = self.dedup_state.reuse_if_cached(&path, &stat, self.cache_check);
+            #[cfg(feature = "redb")]
+            let lookup = reuse_stored(&self.store, &self.path_map, lookup, &path, &stat, self.cache_check, |hf| self.dedup_state.add_hashed_file(hf));
+            match lookup {
+                CacheLookup::Hit => self.monitor.cache_hit(),
+                lookup => {
+                    #[cfg(feature = "xattr")]
+                    if let Some(hf) = self.xattr.then(|| cache::xattr::load(&path, &stat, self.cache_check)).flatten() {
+                        self.monitor.cache_hit();
+                        self.dedup_state.add_hashed_file(hf);
+                        continue;
+                    }
+                    let expected = self.monitor.cache_missed(&path, lookup);
+                    #[cfg(feature = "xattr")]
+                    let xattr = self.stores_xattr(&path);
                     let txc = tx.clone();
                     pool.execute(move|| {
-                        if let Ok(hf) = HashedFile::new(path,modified) {
-                            txc.send(Some(hf)).unwrap();
-                        } else {
-                            txc.send(None).unwrap();
-                        }
+                        let result = HashedFile::new(path.clone(),stat).map_err(|e| (path, e));
+                        #[cfg(feature = "xattr")]
+                        let result = result.map(|hf| if xattr { cache::xattr::store(hf) } else { hf });
+                        txc.send((result, expected)).unwrap();
                     });
-                }
+                },
             }
         }
         drop(tx);
-        for hfo in rx {
-            if let Some(hf) = hfo {
-                self.dedup_state.add_hashed_file(hf);
+        for (result, expected) in rx {
+            match result {
+                Ok(hf) => {
+                    self.monitor.hashed(hf.size());
+                    self.monitor.verified(&hf, expected);
+                    self.dedup_state.add_hashed_file(hf);
+                },
+                Err((path, e)) => self.monitor.error(&path, &e),
+            }
+        }
+        self.monitor.finish();
+        self.monitor.timed(|t| &mut t.scan, start.elapsed());
+        Ok(self.collect_duplicates())
+    }
+    fn prune(&mut self) -> Result<()> {
+        let now = SystemTime::now();
+        let roots : Vec<PathData> = self.roots.iter().map(|r| {
+            let mut path = lexical(&r.path);
+            if self.normalize_path {
+                apply_path_normalization(&mut path);
             }
+            path
+        }).collect();
+        let policy = &self.prune_policy;
+        let monitor = &self.monitor;
+        let decide = |hf : &HashedFile, seen : bool| {
+            // the files skipped by the modification time filter were found too
+            let seen = seen || monitor.was_filtered(hf.path());
+            let under_roots = roots.iter().any(|r| lexical(hf.path()).starts_with(r));
+            let reason = policy.check(hf, seen, under_roots, now);
+            #[cfg(feature = "verbose")]
+            if let Some(reason) = reason {
+                vprintln!(2,"pruning cache entry ({:?}): {}",reason,hf.path().display());
+            }
+            reason
+        };
+        let mut pruned = PruneStats::default();
+        // the entries of a store are not in memory: the ones found during
+        // the run are, but they are counted with the store
+        #[cfg(feature = "redb")]
+        if let Some(store) = &self.store {
+            let in_memory = self.dedup_state.cached_files();
+            let seen : std::collections::HashSet<&PathData> = in_memory.iter().map(|hf| hf.path()).collect();
+            store.prune(&self.path_map, |hf| {
+                let reason = decide(hf, seen.contains(hf.path()));
+                pruned.add(reason);
+                reason.is_some()
+            })?;
+            self.dedup_state.prune(|hf, seen| decide(hf, seen).is_some());
+            self.pruned = pruned;
+            return Ok(());
         }
-        Ok(self.dedup_state.duplicates())
+        self.dedup_state.prune(|hf, seen| {
+            let reason = decide(hf, seen);
+            pruned.add(reason);
+            reason.is_some()
+        });
+        self.pruned = pruned;
+        Ok(())
+    }
+    fn start(&self) -> Instant {
+        let roots = self.roots.iter().filter(|r| !r.reference).map(|r| r.path.as_path()).collect();
+        let references = self.roots.iter().filter(|r| r.reference).map(|r| r.path.as_path()).collect();
+        self.monitor.start(roots, references);
+        Instant::now()
+    }
+    /// Gather the duplicate groups from the state, tell each file which root
+    /// it comes from, flag the files found under reference roots, drop
+    /// the groups that only contain those and sort everything
+    fn collect_duplicates(&self) -> Vec<Duplicates> {
+        let start = Instant::now();
+        let mut duplicates = self.dedup_state.duplicates();
+        for dup in duplicates.iter_mut() {
+            for file in dup.files_mut() {
+                let root = self.root_of(file.path());
+                file.set_reference(root.is_some_and(|r| r.reference));
+                file.set_root(root.map(|r| r.path.clone()));
+                file.set_file_id(file_id(file.path()));
+            }
+            sort::sort_paths(dup, self.path_order);
+        }
+        duplicates.retain(|dup| dup.has_candidates());
+        sort::sort_groups(&mut duplicates, self.group_order);
+        self.monitor.timed(|t| &mut t.grouping, start.elapsed());
+        duplicates
+    }
+    /// Whether to write the digest of a freshly hashed file in its extended
+    /// attributes: never for reference folders, which are left untouched
+    #[cfg(feature = "xattr")]
+    fn stores_xattr(&self, path : &PathData) -> bool {
+        self.xattr && !self.root_of(path).is_some_and(|r| r.reference)
+    }
+    /// The innermost root containing the path (roots can be nested)
+    fn root_of(&self, path : &PathData) -> Option<&Root> {
+        let path = lexical(path);
+        self.roots.iter()
+            .map(|r| {
+                let mut rpath = lexical(&r.path);
+                if self.normalize_path {
+                    apply_path_normalization(&mut rpath);
+                }
+                (r, rpath)
+            })
+            .filter(|(_, rpath)| path.starts_with(rpath))
+            .max_by_key(|(_, rpath)| rpath.components().count())
+            .map(|(r, _)| r)
     }
 }
 
 impl Default for Deduplicator {
     fn default() -> Self {
         Self {
-            dirs : Vec::<PathData>::default(),
+            roots : Vec::<Root>::default(),
             dedup_state : DedupState::new(),
             normalize_path : false,
             threads : None,
+            mtime_filter : MtimeFilter::default(),
+            monitor : Monitor::default(),
+            group_order : SortOrder::default(),
+            path_order : SortOrder::default(),
+            cache_check : CacheCheck::default(),
+            prune_policy : PrunePolicy::default(),
+            pruned : PruneStats::default(),
+            path_map : PathMap::default(),
+            artifacts : Artifacts::new(),
+            cache_compression : None,
+            cache_file : None,
+            empty_cache : false,
+            #[cfg(feature = "xattr")]
+            xattr : false,
+            #[cfg(feature = "redb")]
+            store : None,
+            cache_stamp : None,
         }
     }
 }
 
+/// Walk all the roots and return the regular files found along with their
+/// metadata. A folder that is itself another root is skipped, it
+/// gets walked on its own so that each file is seen only once.
+/// Files rejected by the modification time filter are counted and skipped,
+/// the files written by fdedup are skipped.
+fn walk<'a>(roots : &'a [Root], normalize_path : bool, filter : &'a MtimeFilter, artifacts : &'a Artifacts, monitor : &'a Monitor) -> impl Iterator<Item=(PathData, FileStat)> + 'a {
+    let now = SystemTime::now();
+    let lroots : Vec<PathData> = roots.iter().map(|r| lexical(&r.path)).collect();
+    roots.iter().flat_map(move |root| {
+        let lroots = lroots.clone();
+        walkdir::WalkDir::new(&root.path).into_iter()
+            .filter_entry(move |e| {
+                if e.depth() > 0 && artifacts.contains(e.path()) {
+                    vprintln!(2,"skipping fdedup file: {}",e.path().display());
+                    return false;
+                }
+                e.depth() == 0 || !e.file_type().is_dir() || !lroots.contains(&lexical(e.path()))
+            })
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(move |entry| {
+                let stat = entry.metadata().map(|meta| FileStat::from_metadata(&meta)).unwrap_or_default();
+                let mut path = entry.path().to_owned();
+                if normalize_path {
+                    apply_path_normalization(&mut path);
+                }
+                match filter.check(&stat.modified, now) {
+                    MtimeCheck::Keep => {},
+                    MtimeCheck::OutOfRange => {
+                        stats::add(&monitor.counters.skipped_out_of_range, 1);
+                        monitor.filtered(&path);
+                        return None;
+                    },
+                    MtimeCheck::TooRecent => {
+                        vprintln!(1,"skipping recently modified file: {}",entry.path().display());
+                        stats::add(&monitor.counters.skipped_recent, 1);
+                        monitor.filtered(&path);
+                        return None;
+                    },
+                }
+                monitor.seen();
+                Some((path, stat))
+            })
+    })
+}
+
+/// Look a file that is not in memory up in the store (if any): an entry
+/// that is still valid is handed to add
+#[cfg(feature = "redb")]
+fn reuse_stored<F>(store : &Option<cache::store::CacheStore>, map : &PathMap, lookup : CacheLookup, path : &std::path::Path, stat : &FileStat, check : CacheCheck, add : F) -> CacheLookup where F : FnOnce(HashedFile) {
+    let (CacheLookup::Miss, Some(store)) = (&lookup, store) else {
+        return lookup;
+    };
+    match store.get(path, map) {
+        Ok(Some(cached)) => {
+            let lookup = check.lookup(&cached, stat);
+            if lookup == CacheLookup::Hit {
+                add(cached);
+            }
+            lookup
+        },
+        Ok(None) => CacheLookup::Miss,
+        Err(_e) => {
+            vprintln!(1,"cache store lookup failed for {}: {:#}",path.display(),_e);
+            CacheLookup::Miss
+        },
+    }
+}
+
+fn cache_stamp(fname : &PathData) -> Option<FileStat> {
+    std::fs::metadata(fname).ok().map(|meta| FileStat::from_metadata(&meta))
+}
+
+#[cfg(unix)]
+fn file_id(path : &PathData) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(path).ok().map(|meta| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_path : &PathData) -> Option<FileId> {
+    None
+}
+
+/// Path without the "." components, for comparing paths given in different ways
+fn lexical(path : &std::path::Path) -> PathData {
+    path.components().filter(|c| *c != std::path::Component::CurDir).collect()
+}
+
 fn apply_path_normalization(path: &mut PathData) {
     if std::path::MAIN_SEPARATOR != '/' {
         // if normalize_path and the OS path separator is not '/' try to convert to that
@@ -163,3 +652,90 @@ fn available_parallelism() -> usize {
     1
 }
 
+#[cfg(all(test, any(feature = "xattr", feature = "redb")))]
+mod tests {
+    use super::*;
+
+    /// An empty folder under the temporary one, removed when dropped
+    struct TempDir(PathData);
+
+    impl TempDir {
+        fn new(name : &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("fdedup-{}-{}", name, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[cfg(feature = "xattr")]
+    fn ctime(path : &std::path::Path) -> (i64, i64) {
+        use std::os::unix::fs::MetadataExt;
+        let meta = std::fs::metadata(path).unwrap();
+        (meta.ctime(), meta.ctime_nsec())
+    }
+
+    #[cfg(feature = "xattr")]
+    fn has_attributes(path : &std::path::Path) -> bool {
+        ::xattr::list(path).unwrap().any(|name| name.to_str().is_some_and(|n| n.starts_with(cache::xattr::PREFIX)))
+    }
+
+    #[cfg(feature = "xattr")]
+    #[test]
+    fn xattr_leaves_reference_files_alone() {
+        let dir = TempDir::new("xattr");
+        let (scanned, reference) = (dir.0.join("scanned"), dir.0.join("reference"));
+        std::fs::create_dir_all(&scanned).unwrap();
+        std::fs::create_dir_all(&reference).unwrap();
+        std::fs::write(scanned.join("copy"), b"same content").unwrap();
+        std::fs::write(reference.join("original"), b"same content").unwrap();
+        let before = ctime(&reference.join("original"));
+        std::thread::sleep(Duration::from_millis(20));
+        let duplicates = Deduplicator::builder().root(&scanned).reference(&reference).disable_cache(true).xattr(true)
+            .build().unwrap().run().unwrap();
+        assert_eq!(duplicates.len(), 1);
+        // the file system of the temporary folder may not have extended attributes
+        if has_attributes(&scanned.join("copy")) {
+            assert!(!has_attributes(&reference.join("original")));
+            assert_eq!(ctime(&reference.join("original")), before);
+        }
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn a_store_not_read_is_only_updated() {
+        let dir = TempDir::new("store");
+        let (scanned, fname) = (dir.0.join("scanned"), dir.0.join("cache.redb"));
+        std::fs::create_dir_all(&scanned).unwrap();
+        std::fs::write(scanned.join("file"), b"content").unwrap();
+        let other = HashedFile::from_parts(PathData::from("/elsewhere/file"), vec![1; 64], FileStat::default(), None);
+        cache::store::CacheStore::open(&fname, &mut PathMap::default()).unwrap().update([&other], &PathMap::default()).unwrap();
+        let mut dedup = Deduplicator::builder().root(&scanned).cache_file(&fname).empty_cache(true).build().unwrap();
+        dedup.load_cache();
+        dedup.run().unwrap();
+        dedup.save_cache().unwrap();
+        let mut paths : Vec<_> = cache::read(&fname, &mut PathMap::default()).unwrap().iter().map(|hf| hf.path().clone()).collect();
+        paths.sort();
+        assert_eq!(paths, [PathData::from("/elsewhere/file"), scanned.join("file")]);
+    }
+
+    #[cfg(feature = "redb")]
+    #[test]
+    fn a_store_open_elsewhere_is_waited_for() {
+        let dir = TempDir::new("store-wait");
+        let fname = dir.0.join("cache.redb");
+        let store = cache::store::CacheStore::open(&fname, &mut PathMap::default()).unwrap();
+        let closing = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            drop(store);
+        });
+        assert!(cache::read(&fname, &mut PathMap::default()).unwrap().is_empty());
+        closing.join().unwrap();
+    }
+}
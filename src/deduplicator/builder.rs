@@ -0,0 +1,343 @@
+use std::time::{Duration,SystemTime};
+
+use crate::types::PathData;
+use crate::cache::{location,CacheCheck,Compression,PrunePolicy};
+use crate::filter::MtimeFilter;
+use crate::event::EventHandler;
+use crate::sort::{SortOrder,GroupKey,PathKey};
+use super::Deduplicator;
+
+/// Configuration of a Deduplicator, checked as a whole by build. Covers
+/// everything the command line can set, so that an application embedding
+/// fdedup doesn't need args::Args. The cache file it chooses is read by
+/// Deduplicator::load_cache and written by Deduplicator::save_cache.
+#[derive(Default)]
+pub struct DeduplicatorBuilder {
+    roots : Vec<PathData>,
+    references : Vec<PathData>,
+    threads : Option<usize>,
+    normalize_path : bool,
+    mtime_filter : MtimeFilter,
+    group_order : SortOrder<GroupKey>,
+    path_order : SortOrder<PathKey>,
+    disable_cache : bool,
+    empty_cache : bool,
+    cache_file : Option<PathData>,
+    cache_in_root : bool,
+    cache_check : CacheCheck,
+    prune_policy : PrunePolicy,
+    cache_root : Option<PathData>,
+    remaps : Vec<(PathData, PathData)>,
+    cache_compression : Option<Compression>,
+    #[cfg(feature = "xattr")]
+    xattr : bool,
+    excluded : Vec<PathData>,
+    event_handler : Option<EventHandler>,
+    progress_interval : Option<Duration>,
+}
+
+/// Why a configuration was rejected by DeduplicatorBuilder::build
+#[derive(Debug,Clone,PartialEq,Eq)]
+pub enum BuildError {
+    /// no folder to scan
+    NoRoots,
+    /// a folder to scan (or reference folder) doesn't exist
+    RootNotFound(PathData),
+    /// threads(0)
+    ZeroThreads,
+    /// a number of threads was given to a build without the threads feature
+    ThreadsUnsupported(usize),
+    /// the modification time window is empty (newer_than is not before older_than)
+    EmptyTimeRange { newer_than : SystemTime, older_than : SystemTime },
+    /// cache options that can't be used together
+    ConflictingCacheOptions(&'static str),
+    /// a remap with an empty prefix
+    InvalidRemap(PathData, PathData),
+    /// the compression needs a feature this build doesn't have
+    CompressionUnsupported(Compression),
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuildError::NoRoots => write!(f, "no folder to scan"),
+            BuildError::RootNotFound(path) => write!(f, "folder not found: {}", path.display()),
+            BuildError::ZeroThreads => write!(f, "the number of threads must be at least 1"),
+            BuildError::ThreadsUnsupported(threads) => write!(f, "{} threads requested but this fdedup is single threaded (built without the threads feature)", threads),
+            BuildError::EmptyTimeRange { newer_than, older_than } => write!(f, "no file can be newer than {} and older than {}",
+                humantime::format_rfc3339_seconds(*newer_than), humantime::format_rfc3339_seconds(*older_than)),
+            BuildError::ConflictingCacheOptions(options) => write!(f, "conflicting cache options: {}", options),
+            BuildError::InvalidRemap(old, new) => write!(f, "invalid remap: {}={} (the prefixes can't be empty)", old.display(), new.display()),
+            BuildError::CompressionUnsupported(compression) => write!(f, "{} cache compression is not supported by this fdedup (built without the {} feature)", compression, compression),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl DeduplicatorBuilder {
+    /// Add a folder to scan
+    pub fn root<S>(mut self, dir : S) -> Self where S : Into<PathData> {
+        self.roots.push(dir.into());
+        self
+    }
+    /// Add a read-only reference folder (see Deduplicator::add_reference_dir)
+    pub fn reference<S>(mut self, dir : S) -> Self where S : Into<PathData> {
+        self.references.push(dir.into());
+        self
+    }
+    /// Number of hashing threads (default is the number of cores)
+    pub fn threads(mut self, threads : usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+    /// Convert the path separators to / (see Deduplicator::set_normalize_path)
+    pub fn normalize_path(mut self, normalize : bool) -> Self {
+        self.normalize_path = normalize;
+        self
+    }
+    /// Only scan the files with a modification time accepted by the filter
+    pub fn mtime_filter(mut self, filter : MtimeFilter) -> Self {
+        self.mtime_filter = filter;
+        self
+    }
+    /// Order of the groups returned by run (default is ascending size)
+    pub fn group_order(mut self, order : SortOrder<GroupKey>) -> Self {
+        self.group_order = order;
+        self
+    }
+    /// Order of the paths inside each group (default is by name)
+    pub fn path_order(mut self, order : SortOrder<PathKey>) -> Self {
+        self.path_order = order;
+        self
+    }
+    /// No cache file: load_cache and save_cache do nothing
+    pub fn disable_cache(mut self, disable : bool) -> Self {
+        self.disable_cache = disable;
+        self
+    }
+    /// load_cache starts with an empty cache (save_cache still writes it)
+    pub fn empty_cache(mut self, empty : bool) -> Self {
+        self.empty_cache = empty;
+        self
+    }
+    /// Use this cache file instead of the one found by cache::location::discover
+    pub fn cache_file<S>(mut self, fname : S) -> Self where S : Into<PathData> {
+        self.cache_file = Some(fname.into());
+        self
+    }
+    /// Keep the cache in the first folder instead of the per-user cache
+    pub fn cache_in_root(mut self, in_root : bool) -> Self {
+        self.cache_in_root = in_root;
+        self
+    }
+    /// How cached digests are validated (default is strict)
+    pub fn cache_check(mut self, check : CacheCheck) -> Self {
+        self.cache_check = check;
+        self
+    }
+    /// Which cache entries save_cache drops (default is none)
+    pub fn prune_policy(mut self, policy : PrunePolicy) -> Self {
+        self.prune_policy = policy;
+        self
+    }
+    /// Store the cached paths relative to this folder (default is the first
+    /// folder when the cache file is not given, see cache::PathMap)
+    pub fn cache_root<S>(mut self, root : S) -> Self where S : Into<PathData> {
+        self.cache_root = Some(root.into());
+        self
+    }
+    /// Read the cached paths starting with old as starting with new
+    pub fn remap<S,T>(mut self, old : S, new : T) -> Self where S : Into<PathData>, T : Into<PathData> {
+        self.remaps.push((old.into(), new.into()));
+        self
+    }
+    /// Compression of the cache file (default is to keep the current one)
+    pub fn cache_compression(mut self, compression : Compression) -> Self {
+        self.cache_compression = Some(compression);
+        self
+    }
+    /// Also keep the digests in extended attributes of the files (see cache::xattr)
+    #[cfg(feature = "xattr")]
+    pub fn xattr(mut self, xattr : bool) -> Self {
+        self.xattr = xattr;
+        self
+    }
+    /// Never scan this file nor its lock and temporary files (the cache
+    /// file is always excluded)
+    pub fn exclude<S>(mut self, path : S) -> Self where S : Into<PathData> {
+        self.excluded.push(path.into());
+        self
+    }
+    /// Receive the events of the runs (see event::Event)
+    pub fn event_handler(mut self, handler : EventHandler) -> Self {
+        self.event_handler = Some(handler);
+        self
+    }
+    /// Minimum time between two progress events (default is event::DEFAULT_PROGRESS_INTERVAL)
+    pub fn progress_interval(mut self, interval : Duration) -> Self {
+        self.progress_interval = Some(interval);
+        self
+    }
+
+    /// Check the configuration and create the deduplicator. Without a
+    /// cache file, the cache of the folders is found (and its folder
+    /// created) as cache::location::discover says.
+    pub fn build(self) -> Result<Deduplicator, BuildError> {
+        self.validate()?;
+        let mut dedup = Deduplicator::default();
+        for root in self.roots {
+            dedup.add_dir(root);
+        }
+        for reference in self.references {
+            dedup.add_reference_dir(reference);
+        }
+        dedup.set_threads(self.threads);
+        dedup.set_normalize_path(self.normalize_path);
+        dedup.set_mtime_filter(self.mtime_filter);
+        dedup.set_group_order(self.group_order);
+        dedup.set_path_order(self.path_order);
+        dedup.set_cache_check(self.cache_check);
+        dedup.set_prune_policy(self.prune_policy);
+        dedup.set_cache_compression(self.cache_compression);
+        for (old, new) in self.remaps {
+            dedup.add_cache_remap(old, new);
+        }
+        #[cfg(feature = "xattr")]
+        dedup.set_xattr(self.xattr);
+        for path in &self.excluded {
+            dedup.exclude(path);
+        }
+        if !self.disable_cache {
+            let (fname, root) = match self.cache_file {
+                Some(fname) => (fname, self.cache_root),
+                None => {
+                    let folders : Vec<PathData> = dedup.roots.iter().filter(|r| !r.reference).map(|r| r.path.clone()).collect();
+                    let found = location::discover(&folders, self.cache_in_root);
+                    (found.file, self.cache_root.or(Some(found.root)))
+                },
+            };
+            dedup.set_cache_root(root);
+            dedup.exclude(&fname);
+            dedup.cache_file = Some(fname);
+            dedup.empty_cache = self.empty_cache;
+        }
+        dedup.set_event_handler(self.event_handler);
+        if let Some(interval) = self.progress_interval {
+            dedup.set_progress_interval(interval);
+        }
+        Ok(dedup)
+    }
+
+    fn validate(&self) -> Result<(), BuildError> {
+        if self.roots.is_empty() {
+            return Err(BuildError::NoRoots);
+        }
+        if let Some(missing) = self.roots.iter().chain(&self.references).find(|root| !root.exists()) {
+            return Err(BuildError::RootNotFound(missing.clone()));
+        }
+        match self.threads {
+            Some(0) => return Err(BuildError::ZeroThreads),
+            #[cfg(not(feature = "threads"))]
+            Some(threads) if threads > 1 => return Err(BuildError::ThreadsUnsupported(threads)),
+            _ => {},
+        }
+        if let (Some(newer_than), Some(older_than)) = (self.mtime_filter.newer_than(), self.mtime_filter.older_than()) {
+            if newer_than >= older_than {
+                return Err(BuildError::EmptyTimeRange { newer_than, older_than });
+            }
+        }
+        if self.disable_cache && (self.cache_file.is_some() || self.cache_in_root || self.empty_cache) {
+            return Err(BuildError::ConflictingCacheOptions("disable_cache with cache_file, cache_in_root or empty_cache"));
+        }
+        if self.cache_file.is_some() && self.cache_in_root {
+            return Err(BuildError::ConflictingCacheOptions("cache_file with cache_in_root"));
+        }
+        if let Some((old, new)) = self.remaps.iter().find(|(old, new)| old.as_os_str().is_empty() || new.as_os_str().is_empty()) {
+            return Err(BuildError::InvalidRemap(old.clone(), new.clone()));
+        }
+        #[cfg(not(feature = "zstd"))]
+        if let Some(compression @ Compression::Zstd) = self.cache_compression {
+            return Err(BuildError::CompressionUnsupported(compression));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn here() -> DeduplicatorBuilder {
+        Deduplicator::builder().root(env!("CARGO_MANIFEST_DIR")).disable_cache(true)
+    }
+
+    fn error(builder : DeduplicatorBuilder) -> Option<BuildError> {
+        builder.build().err()
+    }
+
+    #[test]
+    fn valid() {
+        let dedup = here().threads(1).reference(env!("CARGO_MANIFEST_DIR")).remap("/old", "/new").build().unwrap();
+        assert!(dedup.cache_file().is_none());
+    }
+
+    #[test]
+    fn roots() {
+        assert_eq!(error(Deduplicator::builder()), Some(BuildError::NoRoots));
+        let missing = PathData::from("/this/folder/does/not/exist");
+        assert_eq!(error(here().root(&missing)), Some(BuildError::RootNotFound(missing.clone())));
+        assert_eq!(error(here().reference(&missing)), Some(BuildError::RootNotFound(missing)));
+    }
+
+    #[test]
+    fn threads() {
+        assert_eq!(error(here().threads(0)), Some(BuildError::ZeroThreads));
+        #[cfg(not(feature = "threads"))]
+        assert_eq!(error(here().threads(4)), Some(BuildError::ThreadsUnsupported(4)));
+        #[cfg(feature = "threads")]
+        assert_eq!(error(here().threads(4)), None);
+    }
+
+    #[test]
+    fn time_range() {
+        let now = SystemTime::now();
+        let mut filter = MtimeFilter::default();
+        filter.set_newer_than(Some(now));
+        filter.set_older_than(Some(now));
+        assert_eq!(error(here().mtime_filter(filter.clone())), Some(BuildError::EmptyTimeRange { newer_than : now, older_than : now }));
+        filter.set_older_than(Some(now + Duration::from_secs(1)));
+        assert_eq!(error(here().mtime_filter(filter)), None);
+    }
+
+    #[test]
+    fn cache_options() {
+        for builder in [here().cache_file("cache.bin"), here().cache_in_root(true), here().empty_cache(true)] {
+            assert!(matches!(error(builder), Some(BuildError::ConflictingCacheOptions(_))));
+        }
+        let both = Deduplicator::builder().root(env!("CARGO_MANIFEST_DIR")).cache_file("cache.bin").cache_in_root(true);
+        assert_eq!(error(both), Some(BuildError::ConflictingCacheOptions("cache_file with cache_in_root")));
+    }
+
+    #[test]
+    fn remaps() {
+        assert_eq!(error(here().remap("", "/new")), Some(BuildError::InvalidRemap(PathData::new(), PathData::from("/new"))));
+        assert_eq!(error(here().remap("/old", "")), Some(BuildError::InvalidRemap(PathData::from("/old"), PathData::new())));
+    }
+
+    #[test]
+    fn compression() {
+        #[cfg(not(feature = "zstd"))]
+        assert_eq!(error(here().cache_compression(Compression::Zstd)), Some(BuildError::CompressionUnsupported(Compression::Zstd)));
+        #[cfg(feature = "zstd")]
+        assert_eq!(error(here().cache_compression(Compression::Zstd)), None);
+        assert_eq!(error(here().cache_compression(Compression::None)), None);
+    }
+
+    #[test]
+    fn messages() {
+        assert_eq!(BuildError::NoRoots.to_string(), "no folder to scan");
+        assert_eq!(BuildError::ZeroThreads.to_string(), "the number of threads must be at least 1");
+        assert_eq!(BuildError::InvalidRemap(PathData::new(), PathData::from("/new")).to_string(), "invalid remap: =/new (the prefixes can't be empty)");
+    }
+}
@@ -12,3 +12,60 @@ pub(crate) use self::single::DedupState;
 pub mod dashmap;
 #[cfg(feature = "dashmap")]
 pub(crate) use self::dashmap::DedupState;
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration,SystemTime,UNIX_EPOCH};
+    use super::DedupState;
+    use crate::hashedfile::{HashedFile,FileStat};
+    use crate::types::PathData;
+
+    fn file(path : &str, byte : u8, modified : u64) -> HashedFile {
+        let stat = FileStat { modified : Some(UNIX_EPOCH + Duration::from_secs(modified)), size : 1, ..Default::default() };
+        HashedFile::from_parts(PathData::from(path), vec![byte; 64], stat, Some(SystemTime::now()))
+    }
+
+    fn hashes(state : &DedupState) -> Vec<(PathData, u8)> {
+        state.cached_files().iter().map(|hf| (hf.path().clone(), hf.hash()[0])).collect()
+    }
+
+    #[test]
+    fn merge_adds_unknown_paths() {
+        let mut state = DedupState::new();
+        assert_eq!(state.merge_cache(vec![file("/a", 1, 10), file("/b", 2, 10)]), 2);
+        assert_eq!(hashes(&state), [(PathData::from("/a"), 1), (PathData::from("/b"), 2)]);
+    }
+
+    #[test]
+    fn merge_keeps_the_files_seen_during_the_run() {
+        let mut state = DedupState::new();
+        state.add_hashed_file(file("/a", 1, 10));
+        // the other process saw a newer file, but this run hashed the file as it is now
+        assert_eq!(state.merge_cache(vec![file("/a", 2, 20), file("/b", 3, 10)]), 1);
+        assert_eq!(hashes(&state), [(PathData::from("/a"), 1), (PathData::from("/b"), 3)]);
+    }
+
+    #[test]
+    fn merge_takes_the_newest_of_the_unseen_files() {
+        let mut state = DedupState::new();
+        state.merge_cache(vec![file("/old", 1, 10), file("/same", 2, 10), file("/new", 3, 30)]);
+        let taken = state.merge_cache(vec![file("/old", 4, 20), file("/same", 5, 10), file("/new", 6, 20)]);
+        assert_eq!(taken, 1);
+        assert_eq!(hashes(&state), [(PathData::from("/new"), 3), (PathData::from("/old"), 4), (PathData::from("/same"), 2)]);
+    }
+
+    #[test]
+    fn merged_entries_are_not_seen() {
+        let mut state = DedupState::new();
+        state.add_hashed_file(file("/a", 1, 10));
+        state.merge_cache(vec![file("/b", 1, 10)]);
+        assert!(state.duplicates().is_empty());
+        let mut seen = vec![];
+        state.prune(|hf, was_seen| {
+            seen.push((hf.path().clone(), was_seen));
+            false
+        });
+        seen.sort();
+        assert_eq!(seen, [(PathData::from("/a"), true), (PathData::from("/b"), false)]);
+    }
+}
@@ -1,10 +1,11 @@
-use std::time::SystemTime;
+use std::collections::HashSet;
 use dashmap::DashMap;
 
 use crate::types::{PathData,FileSize,HashData,Result};
 use crate::verbose::{vprintln};
-use crate::hashedfile::HashedFile;
-use crate::duplicates::Duplicates;
+use crate::hashedfile::{HashedFile,FileStat};
+use crate::cache::{CacheCheck,CacheLookup,Compression,PathMap};
+use crate::duplicates::{Duplicates,DuplicateFile};
 
 #[derive(Debug,Default)]
 pub struct DedupState {
@@ -25,28 +26,25 @@ impl DedupState {
         };
         self.by_path.insert(hf.path().clone(), hf);
     }
-    pub (crate) fn reuse_if_cached(&self, path : &PathData, modified : &Option<SystemTime>) -> bool {
-        if let Some(modified) = modified {
-            if let Some(old) = self.by_path.get(path) {
-                if let Some(oldmod) = old.modified() {
-                    if oldmod == *modified {
-                        let hf = old.clone();
-                        // when using threads it's important to drop old
-                        // after cloning to quickly release the
-                        // read lock on by_path
-                        drop(old);
-                        vprintln!(2,"reusing from cache: {}",hf.path().display());
-                        if let Some(mut v) = self.by_hash.get_mut(hf.hash()) {
-                            v.push(hf.path().clone())
-                        } else {
-                            self.by_hash.insert(hf.hash().clone(), vec!(hf.path().clone()));
-                        };
-                        return true;
-                    }
-                }
-            }
+    pub (crate) fn reuse_if_cached(&self, path : &PathData, stat : &FileStat, check : CacheCheck) -> CacheLookup {
+        let Some(old) = self.by_path.get(path) else {
+            return CacheLookup::Miss;
+        };
+        let lookup = check.lookup(&old, stat);
+        if lookup == CacheLookup::Hit {
+            let hf = old.clone();
+            // when using threads it's important to drop old
+            // after cloning to quickly release the
+            // read lock on by_path
+            drop(old);
+            vprintln!(2,"reusing from cache: {}",hf.path().display());
+            if let Some(mut v) = self.by_hash.get_mut(hf.hash()) {
+                v.push(hf.path().clone())
+            } else {
+                self.by_hash.insert(hf.hash().clone(), vec!(hf.path().clone()));
+            };
         }
-        false
+        lookup
     }
     pub (crate) fn duplicates_with_minsize(& self, minsize : FileSize) -> Vec<Duplicates> {
         let mut result = vec!();
@@ -69,8 +67,8 @@ impl DedupState {
             if group.len() > 1 {
                 let group_info = group[0].clone();
                 if group_info.size() > minsize {
-                    result.push(Duplicates::new(
-                        group.iter().map(|e| e.path().clone()).collect::<Vec<_>>(),
+                    result.push(Duplicates::from_files(
+                        group.iter().map(|e| DuplicateFile::from(&**e)).collect::<Vec<_>>(),
                         hex::encode(group_info.hash()),
                         group_info.size()
                     ))
@@ -83,15 +81,43 @@ impl DedupState {
     pub fn duplicates(& self) -> Vec<Duplicates> {
         self.duplicates_with_minsize(0)
     }
-    pub fn write_cache<S>(&mut self, fname: S) -> Result<()> where S: Into<PathData> {
-        let bytes = bincode::serialize(&self.by_path.iter().map(|kv|kv.value().clone()).collect::<Vec<_>>())?;
-        std::fs::write(fname.into(), &bytes[..])?;
-        Ok(())
+    /// All the entries (hashed this run or loaded from a cache), sorted by path
+    pub fn cached_files(&self) -> Vec<HashedFile> {
+        let mut files : Vec<HashedFile> = self.by_path.iter().map(|kv| kv.value().clone()).collect();
+        files.sort_by(|a, b| a.path().cmp(b.path()));
+        files
+    }
+    /// Drop the entries rejected by the function, which gets each entry
+    /// and whether its file was found during the run
+    pub fn prune<F>(&mut self, mut reject : F) where F : FnMut(&HashedFile, bool) -> bool {
+        let seen : HashSet<PathData> = self.by_hash.iter().flat_map(|kv| kv.value().clone()).collect();
+        self.by_path.retain(|path, hf| !reject(hf, seen.contains(path)));
+    }
+    pub fn write_cache<S>(&mut self, fname: S, map : &PathMap, compression : Compression) -> Result<()> where S: Into<PathData> {
+        crate::cache::write(fname.into(), self.by_path.iter().map(|kv|kv.value().clone()).collect::<Vec<_>>(), map, compression)
+    }
+    /// Add the entries of a cache that another process updated meanwhile:
+    /// unknown paths are added, and for the paths not seen during the run
+    /// the entry of the most recently modified file wins.
+    /// Returns the number of entries taken from the cache.
+    pub fn merge_cache(&mut self, cache : Vec<HashedFile>) -> usize {
+        let seen : HashSet<PathData> = self.by_hash.iter().flat_map(|kv| kv.value().clone()).collect();
+        let mut taken = 0;
+        for hf in cache {
+            let take = match self.by_path.get(hf.path()) {
+                None => true,
+                Some(old) => !seen.contains(hf.path()) && hf.modified() > old.modified(),
+            };
+            if take {
+                vprintln!(1,"merging from cache: {}",hf.path().display());
+                self.by_path.insert(hf.path().clone(), hf);
+                taken += 1;
+            }
+        }
+        taken
     }
-    pub fn read_cache<S>(&mut self, fname: S) -> Result<()> where S: Into<PathData> {
-        let fname = fname.into();
-        let bytes = std::fs::read(fname)?;
-        let cache : Vec<HashedFile> = bincode::deserialize(&bytes[..])?;
+    pub fn read_cache<S>(&mut self, fname: S, map : &mut PathMap) -> Result<()> where S: Into<PathData> {
+        let cache = crate::cache::read(fname.into(), map)?;
         for hf in cache.iter() {
             if !self.by_path.contains_key(hf.path()) {
                 vprintln!(1,"adding to cache: {}",hf.path().display());
@@ -1,5 +1,4 @@
-use std::time::SystemTime;
-use std::collections::HashMap;
+use std::collections::{HashMap,HashSet};
 #[cfg(not(feature = "mutex"))]
 use std::cell::RefCell;
 #[cfg(feature = "mutex")]
@@ -7,8 +6,9 @@ use std::sync::Mutex;
 
 use crate::types::{PathData,FileSize,HashData,Result};
 use crate::verbose::vprintln;
-use crate::hashedfile::HashedFile;
-use crate::duplicates::Duplicates;
+use crate::hashedfile::{HashedFile,FileStat};
+use crate::cache::{CacheCheck,CacheLookup,Compression,PathMap};
+use crate::duplicates::{Duplicates,DuplicateFile};
 
 #[derive(Debug,Default)]
 pub struct DedupState {
@@ -52,36 +52,33 @@ impl DedupState {
         }
         locked!(self.by_path).insert(hf.path().clone(), hf);
     }
-    pub (crate) fn reuse_if_cached(&self, path : &PathData, modified : &Option<SystemTime>) -> bool {
-        if let Some(modified) = modified {
-            let by_path = locked!(self.by_path);
-            if let Some(old) = by_path.get(path) {
-                if let Some(oldmod) = old.modified() {
-                    if oldmod == *modified {
-                        let hf = old.clone();
-                        // when using threads it's important to drop by_path
-                        // to release the lock on it after we cloned the ref
-                        // to old that was pointing inside it
-                        drop(by_path);
-                        vprintln!(2,"reusing from cache: {}",hf.path().display());
-                        let mut by_hash = locked!(self.by_hash); 
-                        if let Some(v) = by_hash.get_mut(hf.hash()) {
-                            v.push(hf.path().clone())
-                        } else {
-                            by_hash.insert(hf.hash().clone(), vec!(hf.path().clone()));
-                        };
-                        return true;
-                    }
-                }
-            }
+    pub (crate) fn reuse_if_cached(&self, path : &PathData, stat : &FileStat, check : CacheCheck) -> CacheLookup {
+        let by_path = locked!(self.by_path);
+        let Some(old) = by_path.get(path) else {
+            return CacheLookup::Miss;
+        };
+        let lookup = check.lookup(old, stat);
+        if lookup == CacheLookup::Hit {
+            let hf = old.clone();
+            // when using threads it's important to drop by_path
+            // to release the lock on it after we cloned the ref
+            // to old that was pointing inside it
+            drop(by_path);
+            vprintln!(2,"reusing from cache: {}",hf.path().display());
+            let mut by_hash = locked!(self.by_hash); 
+            if let Some(v) = by_hash.get_mut(hf.hash()) {
+                v.push(hf.path().clone())
+            } else {
+                by_hash.insert(hf.hash().clone(), vec!(hf.path().clone()));
+            };
         }
-        false
+        lookup
     }
     pub (crate) fn duplicates_with_minsize(& self, minsize : FileSize) -> Vec<Duplicates> {
         let mut result = vec!();
         let by_hash = locked!(self.by_hash);
         let by_path = locked!(self.by_path);
-        let dups_iter = by_hash.iter().filter_map(|(_,x)| {
+        let dups_iter = by_hash.values().filter_map(|x| {
             if (*x).len() > 1 {
                 Some((*x).iter().filter_map(|p| by_path.get(p)))
             } else {
@@ -93,8 +90,8 @@ impl DedupState {
             if group.len() > 1 {
                 let group_info = group[0];
                 if group_info.size() > minsize {
-                    result.push(Duplicates::new(
-                        group.iter().map(|e| e.path().clone()).collect::<Vec<_>>(),
+                    result.push(Duplicates::from_files(
+                        group.iter().map(|e| DuplicateFile::from(&**e)).collect::<Vec<_>>(),
                         hex::encode(group_info.hash()),
                         group_info.size()
                     ))
@@ -108,15 +105,46 @@ impl DedupState {
     pub fn duplicates(& self) -> Vec<Duplicates> {
         self.duplicates_with_minsize(0)
     }
-    pub fn write_cache<S>(&mut self, fname: S) -> Result<()> where S: Into<PathData> {
-        let bytes = bincode::serialize(&locked!(self.by_path).values().collect::<Vec<_>>())?;
-        std::fs::write(fname.into(), &bytes[..])?;
-        Ok(())
+    /// All the entries (hashed this run or loaded from a cache), sorted by path
+    pub fn cached_files(&self) -> Vec<HashedFile> {
+        let mut files : Vec<HashedFile> = locked!(self.by_path).values().cloned().collect();
+        files.sort_by(|a, b| a.path().cmp(b.path()));
+        files
+    }
+    /// Drop the entries rejected by the function, which gets each entry
+    /// and whether its file was found during the run
+    pub fn prune<F>(&mut self, mut reject : F) where F : FnMut(&HashedFile, bool) -> bool {
+        let by_hash = locked!(self.by_hash);
+        let seen : HashSet<&PathData> = by_hash.values().flatten().collect();
+        locked!(self.by_path).retain(|path, hf| !reject(hf, seen.contains(path)));
+    }
+    pub fn write_cache<S>(&mut self, fname: S, map : &PathMap, compression : Compression) -> Result<()> where S: Into<PathData> {
+        crate::cache::write(fname.into(), locked!(self.by_path).values(), map, compression)
+    }
+    /// Add the entries of a cache that another process updated meanwhile:
+    /// unknown paths are added, and for the paths not seen during the run
+    /// the entry of the most recently modified file wins.
+    /// Returns the number of entries taken from the cache.
+    pub fn merge_cache(&mut self, cache : Vec<HashedFile>) -> usize {
+        let by_hash = locked!(self.by_hash);
+        let seen : HashSet<&PathData> = by_hash.values().flatten().collect();
+        let mut by_path = locked!(self.by_path);
+        let mut taken = 0;
+        for hf in cache {
+            let take = match by_path.get(hf.path()) {
+                None => true,
+                Some(old) => !seen.contains(hf.path()) && hf.modified() > old.modified(),
+            };
+            if take {
+                vprintln!(1,"merging from cache: {}",hf.path().display());
+                by_path.insert(hf.path().clone(), hf);
+                taken += 1;
+            }
+        }
+        taken
     }
-    pub fn read_cache<S>(&mut self, fname: S) -> Result<()> where S: Into<PathData> {
-        let fname = fname.into();
-        let bytes = std::fs::read(fname)?;
-        let cache : Vec<HashedFile> = bincode::deserialize(&bytes[..])?;
+    pub fn read_cache<S>(&mut self, fname: S, map : &mut PathMap) -> Result<()> where S: Into<PathData> {
+        let cache = crate::cache::read(fname.into(), map)?;
         for hf in cache.iter() {
             if !locked!(self.by_path).contains_key(hf.path()) {
                 vprintln!(1,"adding to cache: {}",hf.path().display());
@@ -1,28 +1,159 @@
+use serde::{Serialize,Serializer,ser::SerializeStruct};
+
+use std::time::SystemTime;
+
 use crate::types::{PathData,FileSize};
+use crate::hashedfile::HashedFile;
+
+#[derive(Debug,Clone)]
+pub struct DuplicateFile {
+    path : PathData,
+    modified : Option<SystemTime>,
+    root : Option<PathData>,
+    reference : bool,
+    file_id : Option<FileId>,
+}
+
+/// Identifies the data of a file on a device (device and inode on Unix):
+/// hard links to the same data share the same id
+pub type FileId = (u64, u64);
+
+impl DuplicateFile {
+    pub fn new(path : PathData) -> Self {
+        Self { path, modified : None, root : None, reference : false, file_id : None }
+    }
+    pub fn path(&self) -> &PathData {
+        &self.path
+    }
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+    /// The scanned folder the file was found in
+    pub fn root(&self) -> Option<&PathData> {
+        self.root.as_ref()
+    }
+    pub(crate) fn set_root(&mut self, root : Option<PathData>) {
+        self.root = root;
+    }
+    /// true if the file lives under a read-only reference root
+    /// (it must never be modified, moved or removed)
+    pub fn is_reference(&self) -> bool {
+        self.reference
+    }
+    pub(crate) fn set_reference(&mut self, reference : bool) {
+        self.reference = reference;
+    }
+    /// None when unknown (or not supported by the OS)
+    pub fn file_id(&self) -> Option<FileId> {
+        self.file_id
+    }
+    pub(crate) fn set_file_id(&mut self, file_id : Option<FileId>) {
+        self.file_id = file_id;
+    }
+}
 
 #[derive(Debug)]
 pub struct Duplicates {
-    paths : Vec<PathData>,
+    files : Vec<DuplicateFile>,
     hex_hash : String,
     size : FileSize,
 }
 
+impl From<&HashedFile> for DuplicateFile {
+    fn from(hf : &HashedFile) -> Self {
+        Self { modified : hf.modified(), ..Self::new(hf.path().clone()) }
+    }
+}
+
 impl Duplicates {
     pub fn new(paths : Vec<PathData>, hex_hash : String, size : FileSize) -> Self {
-        Self { paths, hex_hash, size }
+        Self::from_files(paths.into_iter().map(DuplicateFile::new).collect(), hex_hash, size)
+    }
+    pub fn from_files(files : Vec<DuplicateFile>, hex_hash : String, size : FileSize) -> Self {
+        Self { files, hex_hash, size }
     }
     pub fn size(&self) -> FileSize {
         self.size
     }
-    pub fn paths(&self) -> &Vec<PathData> {
-        &self.paths
+    pub fn count(&self) -> usize {
+        self.files.len()
+    }
+    /// Number of distinct copies of the data: hard links to the same data
+    /// count as one (files with an unknown id are assumed to be distinct)
+    pub fn distinct_count(&self) -> usize {
+        let mut ids : Vec<FileId> = self.files.iter().filter_map(|f| f.file_id()).collect();
+        let unknown = self.files.len() - ids.len();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.len() + unknown
+    }
+    /// bytes that could be reclaimed by keeping a single copy
+    /// (hard links don't waste any space)
+    pub fn redundant_bytes(&self) -> FileSize {
+        self.size * (self.distinct_count() as FileSize).saturating_sub(1)
     }
-    pub fn paths_as_display(&self) -> impl Iterator<Item=std::path::Display> + '_ {
-        self.paths.iter().map(|p| p.display())
+    pub fn files(&self) -> &Vec<DuplicateFile> {
+        &self.files
+    }
+    pub(crate) fn files_mut(&mut self) -> &mut Vec<DuplicateFile> {
+        &mut self.files
+    }
+    pub fn paths(&self) -> impl Iterator<Item=&PathData> + '_ {
+        self.files.iter().map(|f| f.path())
+    }
+    /// The paths as written in the text output (see pathenc::escape_line),
+    /// pathenc::unescape gives back the exact paths
+    pub fn paths_as_display(&self) -> impl Iterator<Item=std::borrow::Cow<'_, str>> + '_ {
+        self.paths().map(|p| crate::pathenc::escape_line(p))
     }
     pub fn hash_as_hex(&self) -> &String {
         &self.hex_hash
     }
+    /// true if the file is the one kept when acting on the group
+    pub fn is_survivor(&self, file : &DuplicateFile) -> bool {
+        self.survivor().path() == file.path()
+    }
+    /// true if at least one file in the group is not a reference
+    /// (groups made only of reference files are not worth reporting)
+    pub fn has_candidates(&self) -> bool {
+        self.files.iter().any(|f| !f.is_reference())
+    }
+    /// The file that is kept when acting on the group:
+    /// the first reference file if there is one, otherwise the first file.
+    pub fn survivor(&self) -> &DuplicateFile {
+        self.files.iter().find(|f| f.is_reference()).unwrap_or(&self.files[0])
+    }
+    /// The files that an action (remove, link, move...) may touch.
+    /// Reference files and the survivor are never part of it.
+    pub fn non_survivors(&self) -> impl Iterator<Item=&DuplicateFile> + '_ {
+        let survivor = self.survivor().path();
+        self.files.iter().filter(move |f| !f.is_reference() && f.path() != survivor)
+    }
+}
+
+impl Serialize for Duplicates {
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+        // paths of the files selected by the function, escaped losslessly
+        struct Paths<'a>(&'a Duplicates, fn(&DuplicateFile) -> bool);
+        impl Serialize for Paths<'_> {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> where S: Serializer {
+                serializer.collect_seq(self.0.files.iter().filter(|f| (self.1)(f)).map(|f| crate::pathenc::escape(f.path())))
+            }
+        }
+        let has_references = self.files.iter().any(|f| f.is_reference());
+        let mut group = serializer.serialize_struct("Duplicates", if has_references { 6 } else { 5 })?;
+        group.serialize_field("hash", &self.hex_hash)?;
+        group.serialize_field("size", &self.size)?;
+        group.serialize_field("count", &self.count())?;
+        group.serialize_field("redundant_bytes", &self.redundant_bytes())?;
+        group.serialize_field("paths", &Paths(self, |_| true))?;
+        if has_references {
+            group.serialize_field("references", &Paths(self, |f| f.is_reference()))?;
+        } else {
+            group.skip_field("references")?;
+        }
+        group.end()
+    }
 }
 
 impl std::fmt::Display for Duplicates {
@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+
+use crate::duplicates::Duplicates;
+use crate::hashedfile::HashedFile;
+use crate::cache::{CacheLookup,Invalidation};
+use crate::types::{HashData,PathData};
+use crate::stats::{self, Counters, Summary, Timings};
+use crate::verbose::vprintln;
+
+/// Something that happened during a scan, for consumers that follow a scan
+/// while it runs (see Deduplicator::set_event_handler)
+#[derive(Debug,Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    ScanStart {
+        #[serde(serialize_with = "crate::pathenc::serialize_all")]
+        roots : Vec<&'a Path>,
+        #[serde(serialize_with = "crate::pathenc::serialize_all")]
+        references : Vec<&'a Path>,
+    },
+    Progress(Progress),
+    Error {
+        #[serde(serialize_with = "crate::pathenc::serialize")]
+        path : &'a Path,
+        message : String,
+    },
+    /// written by output::ndjson once the scan is over: a group is only
+    /// complete when every file has been hashed
+    Group(&'a Duplicates),
+    Summary(&'a Summary),
+}
+
+/// Where a scan is at
+#[derive(Debug,Clone,Default,Serialize)]
+pub struct Progress {
+    pub files_seen : u64,
+    pub files_hashed : u64,
+    pub cache_hits : u64,
+    pub bytes_hashed : u64,
+    pub errors : u64,
+}
+
+pub type EventHandler = Box<dyn Fn(&Event) + Send + Sync>;
+
+pub const DEFAULT_PROGRESS_INTERVAL : Duration = Duration::from_secs(1);
+
+/// Keeps the counters of a run and forwards the events to the handler
+pub(crate) struct Monitor {
+    pub(crate) counters : Counters,
+    handler : Option<EventHandler>,
+    progress_interval : Duration,
+    last_progress : Mutex<Instant>,
+    pub(crate) timings : Mutex<Timings>,
+    /// paths of the files skipped by the modification time filter, only
+    /// kept when the prune policy needs them (see record_filtered)
+    filtered : Option<Mutex<HashSet<PathData>>>,
+}
+
+impl Monitor {
+    pub(crate) fn set_handler(&mut self, handler : Option<EventHandler>) {
+        self.handler = handler;
+    }
+    pub(crate) fn set_progress_interval(&mut self, interval : Duration) {
+        self.progress_interval = interval;
+    }
+    /// Keep the paths of the files skipped by the modification time filter:
+    /// they were found, so pruning the unseen entries must keep theirs
+    pub(crate) fn record_filtered(&mut self, record : bool) {
+        self.filtered = record.then(Mutex::default);
+    }
+    fn emit(&self, event : &Event) {
+        if let Some(handler) = &self.handler {
+            handler(event);
+        }
+    }
+    pub(crate) fn start(&self, roots : Vec<&Path>, references : Vec<&Path>) {
+        self.counters.reset();
+        if let Some(mut filtered) = self.filtered.as_ref().and_then(|f| f.lock().ok()) {
+            filtered.clear();
+        }
+        if let Ok(mut last) = self.last_progress.lock() {
+            *last = Instant::now();
+        }
+        self.emit(&Event::ScanStart { roots, references });
+    }
+    pub(crate) fn seen(&self) {
+        stats::add(&self.counters.files_seen, 1);
+    }
+    /// A file skipped by the modification time filter
+    pub(crate) fn filtered(&self, path : &Path) {
+        if let Some(mut filtered) = self.filtered.as_ref().and_then(|f| f.lock().ok()) {
+            filtered.insert(path.to_owned());
+        }
+    }
+    /// Tell if the file was skipped by the modification time filter during
+    /// the run (always false when the paths are not recorded)
+    pub(crate) fn was_filtered(&self, path : &Path) -> bool {
+        self.filtered.as_ref().and_then(|f| f.lock().ok()).is_some_and(|filtered| filtered.contains(path))
+    }
+    pub(crate) fn cache_hit(&self) {
+        stats::add(&self.counters.cache_hits, 1);
+        self.tick();
+    }
+    /// Count the reason why the cache could not be used for a file, returns
+    /// the digest the file must have when it's hashed to be verified
+    #[cfg_attr(not(feature = "verbose"), allow(unused_variables))]
+    pub(crate) fn cache_missed(&self, path : &Path, lookup : CacheLookup) -> Option<HashData> {
+        match lookup {
+            CacheLookup::Invalid(reason) => {
+                vprintln!(2,"cache entry invalidated ({:?}): {}",reason,path.display());
+                stats::add(&self.counters.invalidated[reason as usize], 1);
+                None
+            },
+            CacheLookup::Verify(hash) => Some(hash),
+            CacheLookup::Hit | CacheLookup::Miss => None,
+        }
+    }
+    /// Compare the digest of a file hashed for verification with the cached one
+    pub(crate) fn verified(&self, hf : &HashedFile, expected : Option<HashData>) {
+        if expected.is_some_and(|hash| hash != *hf.hash()) {
+            vprintln!(1,"stale cache entry (content changed): {}",hf.path().display());
+            stats::add(&self.counters.invalidated[Invalidation::Content as usize], 1);
+        }
+    }
+    pub(crate) fn hashed(&self, size : u64) {
+        stats::add(&self.counters.files_hashed, 1);
+        stats::add(&self.counters.bytes_hashed, size);
+        self.tick();
+    }
+    pub(crate) fn error(&self, path : &Path, error : &anyhow::Error) {
+        vprintln!(1,"error hashing {}: {}",path.display(),error);
+        stats::add(&self.counters.errors, 1);
+        self.emit(&Event::Error { path, message : error.to_string() });
+        self.tick();
+    }
+    /// Emit a progress event if the interval has elapsed since the last one
+    fn tick(&self) {
+        if self.handler.is_none() {
+            return;
+        }
+        // another thread holding the lock is already reporting
+        if let Ok(mut last) = self.last_progress.try_lock() {
+            if last.elapsed() >= self.progress_interval {
+                *last = Instant::now();
+                self.emit(&Event::Progress(self.progress()));
+            }
+        }
+    }
+    /// Record the time spent in a phase of the run
+    pub(crate) fn timed<F>(&self, phase : F, duration : Duration) where F : FnOnce(&mut Timings) -> &mut Duration {
+        if let Ok(mut timings) = self.timings.lock() {
+            *phase(&mut timings) = duration;
+        }
+    }
+    /// Emit a last progress event at the end of the scan
+    pub(crate) fn finish(&self) {
+        self.emit(&Event::Progress(self.progress()));
+    }
+    pub(crate) fn progress(&self) -> Progress {
+        Progress {
+            files_seen : stats::get(&self.counters.files_seen),
+            files_hashed : stats::get(&self.counters.files_hashed),
+            cache_hits : stats::get(&self.counters.cache_hits),
+            bytes_hashed : stats::get(&self.counters.bytes_hashed),
+            errors : stats::get(&self.counters.errors),
+        }
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self {
+            counters : Counters::default(),
+            handler : None,
+            progress_interval : DEFAULT_PROGRESS_INTERVAL,
+            last_progress : Mutex::new(Instant::now()),
+            timings : Mutex::new(Timings::default()),
+            filtered : None,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::{SystemTime, Duration};
+
+use crate::types::Result;
+
+/// Selects the files taking part in the scan based on their modification time
+#[derive(Debug,Clone,Default)]
+pub struct MtimeFilter {
+    newer_than : Option<SystemTime>,
+    older_than : Option<SystemTime>,
+    settle : Option<Duration>,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum MtimeCheck {
+    Keep,
+    /// outside of the --newer-than/--older-than window
+    OutOfRange,
+    /// modified within the settle time (probably still being written)
+    TooRecent,
+}
+
+impl MtimeFilter {
+    pub fn set_newer_than(&mut self, when : Option<SystemTime>) {
+        self.newer_than = when;
+    }
+    pub fn set_older_than(&mut self, when : Option<SystemTime>) {
+        self.older_than = when;
+    }
+    pub fn set_settle(&mut self, settle : Option<Duration>) {
+        self.settle = settle;
+    }
+    pub fn newer_than(&self) -> Option<SystemTime> {
+        self.newer_than
+    }
+    pub fn older_than(&self) -> Option<SystemTime> {
+        self.older_than
+    }
+    pub fn settle(&self) -> Option<Duration> {
+        self.settle
+    }
+    pub fn is_active(&self) -> bool {
+        self.newer_than.is_some() || self.older_than.is_some() || self.settle.is_some()
+    }
+    /// Check a modification time against the filter.
+    /// Files without a modification time can't be in a time window
+    /// but are not considered recent.
+    pub fn check(&self, modified : &Option<SystemTime>, now : SystemTime) -> MtimeCheck {
+        match modified {
+            None if self.newer_than.is_some() || self.older_than.is_some() => MtimeCheck::OutOfRange,
+            None => MtimeCheck::Keep,
+            Some(modified) => {
+                if let Some(settle) = self.settle {
+                    // a modification time in the future is as suspicious as a recent one
+                    if now.duration_since(*modified).map_or(true, |age| age < settle) {
+                        return MtimeCheck::TooRecent;
+                    }
+                }
+                if self.newer_than.is_some_and(|t| *modified <= t) || self.older_than.is_some_and(|t| *modified >= t) {
+                    return MtimeCheck::OutOfRange;
+                }
+                MtimeCheck::Keep
+            },
+        }
+    }
+}
+
+/// Parse an absolute date ("2023-03-01", "2023-03-01 12:30:00", RFC 3339)
+/// or a duration relative to now ("90min", "2days 12h", "1week")
+pub fn parse_time(s : &str, now : SystemTime) -> Result<SystemTime> {
+    let s = s.trim();
+    if let Ok(t) = humantime::parse_rfc3339_weak(s) {
+        return Ok(t);
+    }
+    if let Ok(t) = humantime::parse_rfc3339_weak(&format!("{} 00:00:00",s)) {
+        return Ok(t);
+    }
+    match humantime::parse_duration(s) {
+        Ok(d) => now.checked_sub(d).ok_or_else(|| anyhow::format_err!("time out of range: {}",s)),
+        Err(_) => Err(anyhow::format_err!("invalid date or duration: {} (examples: 2023-03-01, \"2023-03-01 12:30:00\", 3days, 12h)",s)),
+    }
+}
+
+/// Parse a duration such as 30days or 12h
+pub fn parse_age(s : &str) -> Result<Duration> {
+    humantime::parse_duration(s.trim())
+        .map_err(|_| anyhow::format_err!("invalid duration: {} (examples: 30days, 12h, 1week)",s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+    use crate::deduplicator::{Deduplicator,BuildError};
+
+    const HOUR : Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn dates_and_durations() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(parse_time("2023-03-01", now).unwrap(), humantime::parse_rfc3339("2023-03-01T00:00:00Z").unwrap());
+        assert_eq!(parse_time("2023-03-01 12:30:00", now).unwrap(), humantime::parse_rfc3339("2023-03-01T12:30:00Z").unwrap());
+        assert_eq!(parse_time("3days", now).unwrap(), now - 72 * HOUR);
+        assert_eq!(parse_time(" 12h ", now).unwrap(), now - 12 * HOUR);
+        let error = parse_time("yesterday", now).unwrap_err();
+        assert!(error.to_string().starts_with("invalid date or duration: yesterday"), "{}", error);
+        assert_eq!(parse_age("30days").unwrap(), 720 * HOUR);
+        assert!(parse_age("2023-03-01").is_err());
+    }
+
+    #[test]
+    fn window() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut filter = MtimeFilter::default();
+        assert_eq!(filter.check(&None, now), MtimeCheck::Keep);
+        filter.set_newer_than(Some(now - 48 * HOUR));
+        filter.set_older_than(Some(now - 24 * HOUR));
+        assert_eq!(filter.check(&Some(now - 36 * HOUR), now), MtimeCheck::Keep);
+        // both bounds are exclusive
+        assert_eq!(filter.check(&Some(now - 48 * HOUR), now), MtimeCheck::OutOfRange);
+        assert_eq!(filter.check(&Some(now - 24 * HOUR), now), MtimeCheck::OutOfRange);
+        assert_eq!(filter.check(&None, now), MtimeCheck::OutOfRange);
+    }
+
+    #[test]
+    fn settle_cutoff() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut filter = MtimeFilter::default();
+        filter.set_settle(Some(HOUR));
+        assert!(filter.is_active());
+        assert_eq!(filter.check(&Some(now - 2 * HOUR), now), MtimeCheck::Keep);
+        assert_eq!(filter.check(&Some(now - HOUR), now), MtimeCheck::Keep);
+        assert_eq!(filter.check(&Some(now - HOUR + Duration::from_secs(1)), now), MtimeCheck::TooRecent);
+        assert_eq!(filter.check(&Some(now + HOUR), now), MtimeCheck::TooRecent);
+        // files without a modification time are not recent
+        assert_eq!(filter.check(&None, now), MtimeCheck::Keep);
+        // too recent wins over out of range
+        filter.set_older_than(Some(now - 2 * HOUR));
+        assert_eq!(filter.check(&Some(now), now), MtimeCheck::TooRecent);
+    }
+
+    #[test]
+    fn empty_window() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut filter = MtimeFilter::default();
+        filter.set_newer_than(Some(now - 24 * HOUR));
+        filter.set_older_than(Some(now - 48 * HOUR));
+        assert!(filter.check(&Some(now - 36 * HOUR), now) != MtimeCheck::Keep);
+        let error = Deduplicator::builder().root(env!("CARGO_MANIFEST_DIR")).mtime_filter(filter).build().err().unwrap();
+        assert_eq!(error, BuildError::EmptyTimeRange { newer_than : now - 24 * HOUR, older_than : now - 48 * HOUR });
+        assert_eq!(error.to_string(), "no file can be newer than 2023-11-13T22:13:20Z and older than 2023-11-12T22:13:20Z");
+    }
+}
@@ -2,34 +2,83 @@ use serde::{Serialize,Deserialize};
 use std::{time::SystemTime};
 
 use crate::types::{PathData,FileSize,HashData,Result};
+use crate::duplicates::FileId;
+
+/// What is known about a file without reading it, used to decide if a
+/// cached digest still applies (see cache::CacheCheck)
+#[derive(Debug,Clone,Copy,Default,PartialEq,Eq)]
+#[derive(Serialize, Deserialize)]
+pub struct FileStat {
+    pub modified : Option<SystemTime>,
+    pub size : FileSize,
+    /// device and inode (unix only)
+    pub file_id : Option<FileId>,
+    /// status change time as seconds and nanoseconds (unix only)
+    pub ctime : Option<(i64,i64)>,
+}
+
+impl FileStat {
+    #[cfg(unix)]
+    pub fn from_metadata(meta : &std::fs::Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            modified : meta.modified().ok(),
+            size : meta.len(),
+            file_id : Some((meta.dev(), meta.ino())),
+            ctime : Some((meta.ctime(), meta.ctime_nsec())),
+        }
+    }
+    #[cfg(not(unix))]
+    pub fn from_metadata(meta : &std::fs::Metadata) -> Self {
+        Self { modified : meta.modified().ok(), size : meta.len(), file_id : None, ctime : None }
+    }
+}
 
 #[derive(Debug)]
 #[derive(Serialize, Deserialize)]
 pub struct HashedFile {
     path : PathData,
     hash : HashData,
-    modified : Option<SystemTime>,
-    size : FileSize,
+    stat : FileStat,
+    /// when the digest was computed (unknown for entries migrated from old caches)
+    hashed_at : Option<SystemTime>,
 }
 
 impl HashedFile {
-    pub fn new(path : PathData, modified : Option<SystemTime>) -> Result<Self> {
+    /// Hash the file. The size is the number of bytes actually hashed.
+    pub fn new(path : PathData, stat : FileStat) -> Result<Self> {
         use sha2::{Sha512, Digest};
         use std::{io, fs};
 
         let mut hasher = Sha512::new();
         let mut file = fs::File::open(&path)?;
         let size = io::copy(&mut file, &mut hasher)?;
-        Ok(Self{path, hash : hasher.finalize().to_vec(), modified, size})
+        Ok(Self{path, hash : hasher.finalize().to_vec(), stat : FileStat { size, ..stat }, hashed_at : Some(SystemTime::now())})
+    }
+    pub(crate) fn from_parts(path : PathData, hash : HashData, stat : FileStat, hashed_at : Option<SystemTime>) -> Self {
+        Self{path, hash, stat, hashed_at}
+    }
+    pub(crate) fn with_path(self, path : PathData) -> Self {
+        Self{path, ..self}
+    }
+    #[cfg(feature = "xattr")]
+    pub(crate) fn with_stat(self, stat : FileStat) -> Self {
+        Self{stat, ..self}
     }
     pub fn path(&self) -> &PathData {
         &self.path
     }
     pub fn size(&self) -> FileSize {
-        self.size
+        self.stat.size
     }
     pub fn modified(&self) -> Option<SystemTime> {
-        self.modified
+        self.stat.modified
+    }
+    pub fn stat(&self) -> &FileStat {
+        &self.stat
+    }
+    pub fn hashed_at(&self) -> Option<SystemTime> {
+        self.hashed_at
     }
     pub fn hash(&self) -> &HashData {
         &self.hash
@@ -38,6 +87,6 @@ impl HashedFile {
 
 impl Clone for HashedFile {
     fn clone(&self) -> Self {
-        Self {path: self.path.clone(),hash : self.hash.clone(),modified : self.modified,size : self.size}
+        Self {path: self.path.clone(),hash : self.hash.clone(),stat : self.stat,hashed_at : self.hashed_at}
     }
 }
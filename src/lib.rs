@@ -6,12 +6,21 @@ pub use verbose::set_verbosity;
 
 pub mod duplicates;
 pub use duplicates::Duplicates;
+pub mod pathenc;
+pub mod output;
 pub mod hashedfile;
+pub mod cache;
 pub mod dedupstate;
+pub mod filter;
+pub mod stats;
+pub mod event;
+pub mod sort;
+pub mod report;
 
 pub mod deduplicator;
-pub use deduplicator::Deduplicator;
+pub use deduplicator::{Deduplicator,DeduplicatorBuilder,BuildError};
 
 pub mod args;
 
+/// Name of the cache file kept inside a scanned folder (see cache::location)
 pub const DEFAULT_CACHE_FILE : &str = ".fdedup_cache.bin";
\ No newline at end of file
@@ -1,4 +1,12 @@
-use fdedup::{Deduplicator,Result,args::Args};
+use fdedup::{Deduplicator,Result,args::{Args,Command,CacheCommand}};
+use fdedup::filter::{MtimeFilter,parse_time,parse_age};
+use fdedup::cache::{PrunePolicy,PathMap,maintenance,location};
+use fdedup::output::{write_duplicates,Format,Options,ndjson};
+use fdedup::report::ReportOptions;
+use fdedup::event::Event;
+use std::time::{SystemTime,Duration};
+use std::io::{Write,BufWriter};
+use std::path::Path;
 #[cfg(feature = "verbose")]
 use fdedup::{set_verbosity};
 
@@ -6,22 +14,159 @@ fn main() -> Result<()> {
     let args = Args::new();
     #[cfg(feature = "verbose")]
     set_verbosity(args.verbosity)?;
-    let mut dedup = Deduplicator::default();
-    #[cfg(feature = "threads")]
-    dedup.set_threads(args.threads);
+    if let Some(Command::Cache { mut folders, command }) = args.command {
+        // the commands work on the cache a scan of the same folders uses
+        if folders.is_empty() {
+            folders.push(".".into());
+        }
+        let cache_file = args.cache_file.unwrap_or_else(|| location::discover(&folders, false).file);
+        if command.reads_cache() && !cache_file.exists() {
+            let caches : Vec<String> = location::user_caches().iter().map(|c| format!("\n  {}",c.display())).collect();
+            return Err(anyhow::format_err!("no cache at {} (give the scanned folders with --for or the cache with -c){}{}",
+                cache_file.display(), if caches.is_empty() { "" } else { ", the per-user caches are:" }, caches.concat()));
+        }
+        return run_command(command, &cache_file);
+    }
+    let mut output = Options {
+        format : Format::Text,
+        show_size : args.show_size,
+        same_line : args.same_line,
+        omit_first : args.omit_first,
+        summarize : args.summarize,
+        report : ReportOptions { top_pairs : args.top_pairs, ..Default::default() },
+    };
+    output.format = match args.format {
+        Some(format) => format,
+        None if args.print0 => Format::Print0,
+        None if output.wants_fdupes() || invoked_as_fdupes() => Format::Fdupes,
+        None => Format::Text,
+    };
+    let now = SystemTime::now();
+    let mut mtime_filter = MtimeFilter::default();
+    mtime_filter.set_newer_than(args.newer_than.map(|s| parse_time(&s, now)).transpose()?);
+    mtime_filter.set_older_than(args.older_than.map(|s| parse_time(&s, now)).transpose()?);
+    mtime_filter.set_settle(args.settle.map(Duration::from_secs));
+    let mut builder = Deduplicator::builder()
+        .normalize_path(args.normalize)
+        .group_order(args.sort)
+        .path_order(args.sort_paths)
+        .mtime_filter(mtime_filter)
+        .disable_cache(args.disable_cache)
+        .empty_cache(args.empty_cache)
+        .cache_in_root(args.cache_in_root)
+        .cache_check(args.cache_check)
+        .prune_policy(PrunePolicy {
+            unseen : args.prune_unseen,
+            missing : args.prune_missing,
+            max_age : args.max_age.map(|s| parse_age(&s)).transpose()?,
+        });
+    #[cfg(feature = "xattr")]
+    {
+        builder = builder.xattr(args.xattr);
+    }
     for d in args.folders {
-        dedup.add_dir(d);
+        builder = builder.root(d);
+    }
+    for d in args.reference {
+        builder = builder.reference(d);
+    }
+    if let Some(threads) = args.threads {
+        builder = builder.threads(threads);
+    }
+    if let Some(fname) = args.cache_file {
+        builder = builder.cache_file(fname);
     }
-    dedup.set_normalize_path(args.normalize);
-    if !args.disable_cache && !args.empty_cache {
-        dedup.read_cache(&args.cache_file);
+    if let Some(root) = args.cache_root {
+        builder = builder.cache_root(root);
     }
+    if let Some(compression) = args.cache_compression {
+        builder = builder.cache_compression(compression);
+    }
+    for remap in &args.remap {
+        let (old, new) = PathMap::parse_remap(remap)?;
+        builder = builder.remap(old, new);
+    }
+    let events = args.events;
+    if events {
+        builder = builder.event_handler(Box::new(|event| {
+            let mut out = std::io::stdout().lock();
+            if ndjson::write_event(&mut out, event).is_ok() {
+                let _ = out.flush();
+            }
+        }));
+    }
+    let mut dedup = builder.build()?;
+    dedup.load_cache();
     let duplicates = dedup.run()?;
-    if !args.disable_cache {
-        dedup.write_cache(&args.cache_file)?;
+    dedup.save_cache()?;
+    let summary = dedup.summary(&duplicates);
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    write_duplicates(&mut out, &output, &duplicates, &summary)?;
+    if events {
+        ndjson::write_event(&mut out, &Event::Summary(&summary))?;
+    }
+    out.flush()?;
+    if args.stats {
+        eprint!("{}",summary);
+    } else if summary.skipped_recent > 0 {
+        eprintln!("# skipped {} files modified within the last {} seconds",summary.skipped_recent,args.settle.unwrap_or(0));
     }
-    for dup in duplicates {
-        println!("{}",dup);
+    Ok(())
+}
+
+/// Run a command on the cache file instead of scanning
+fn run_command(command : CacheCommand, cache_file : &Path) -> Result<()> {
+    match command {
+        CacheCommand::Prune { missing, max_age } => {
+            let policy = PrunePolicy { missing, max_age : max_age.map(|s| parse_age(&s)).transpose()?, ..Default::default() };
+            println!("{}", maintenance::prune(cache_file, &policy)?);
+        },
+        CacheCommand::Stats => println!("{}", maintenance::stats(cache_file)?),
+        CacheCommand::List { patterns } => {
+            let filter = maintenance::PathFilter::new(&patterns)?;
+            write_entries(&maintenance::list(cache_file, &filter)?)?;
+        },
+        CacheCommand::Lookup { keys } => {
+            let found = maintenance::lookup(cache_file, &keys)?;
+            if found.is_empty() {
+                return Err(anyhow::format_err!("not found in the cache: {}",keys.join(", ")));
+            }
+            write_entries(&found)?;
+        },
+        CacheCommand::Remove { patterns } => {
+            let filter = maintenance::PathFilter::new(&patterns)?;
+            println!("{} cache entries removed", maintenance::remove(cache_file, &filter)?);
+        },
+        CacheCommand::Merge { sources } => println!("{}", maintenance::merge(cache_file, &sources)?),
+        CacheCommand::Export { output : Some(output) } => {
+            let out = BufWriter::new(std::fs::File::create(&output)?);
+            maintenance::export(cache_file, out)?;
+        },
+        #[cfg(feature = "xattr")]
+        CacheCommand::StripXattr { folders } => {
+            println!("{} files stripped of their fdedup extended attributes", maintenance::strip_xattr(&folders)?);
+        },
+        CacheCommand::Export { output : None } => {
+            let mut out = std::io::stdout().lock();
+            maintenance::export(cache_file, &mut out)?;
+            writeln!(out)?;
+        },
     }
     Ok(())
 }
+
+fn write_entries(files : &[fdedup::hashedfile::HashedFile]) -> Result<()> {
+    let mut out = BufWriter::new(std::io::stdout().lock());
+    for hf in files {
+        maintenance::write_entry(&mut out, hf)?;
+    }
+    Ok(())
+}
+
+/// true when the binary is installed (or linked) under the name of fdupes or jdupes
+/// so that the scripts written for those get the output they expect
+fn invoked_as_fdupes() -> bool {
+    std::env::args_os().next()
+        .and_then(|arg0| std::path::Path::new(&arg0).file_stem().map(|s| s.to_owned()))
+        .is_some_and(|name| name == "fdupes" || name == "jdupes")
+}
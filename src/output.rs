@@ -0,0 +1,105 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::types::Result;
+use crate::duplicates::Duplicates;
+use crate::stats::Summary;
+use crate::report::{DirReport,ReportOptions};
+
+pub mod json;
+pub mod ndjson;
+pub mod csv;
+pub mod fdupes;
+pub mod print0;
+pub mod html;
+
+/// How the duplicate groups are written out
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum Format {
+    /// "# size hash" followed by the paths of the group (one per line)
+    #[default]
+    Text,
+    /// a single JSON document with the groups and a summary
+    Json,
+    /// one JSON object per line for each group (and for each event with --events)
+    Ndjson,
+    /// one row per file: group, hash, size, path, mtime, survivor, reference, root
+    Csv,
+    /// same as csv but tab separated
+    Tsv,
+    /// compatible with fdupes/jdupes: paths of a group on consecutive lines, groups separated by a blank line
+    Fdupes,
+    /// raw paths separated by NUL, groups separated by a double NUL (only the non-survivors with -f)
+    Print0,
+    /// where the redundant bytes are: per directory (du-like) and directory pairs sharing the most
+    Dirs,
+    /// a self-contained HTML page: summary, sortable table of the groups and treemap of the wasted space
+    Html,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "fdupes" => Ok(Format::Fdupes),
+            "print0" => Ok(Format::Print0),
+            "dirs" => Ok(Format::Dirs),
+            "html" => Ok(Format::Html),
+            _ => Err(anyhow::format_err!("unknown output format: {} (expected text, json, ndjson, csv, tsv, fdupes, print0, dirs or html)",s)),
+        }
+    }
+}
+
+/// How the duplicate groups are written out
+/// (the fdupes options only apply to the fdupes format, except for
+/// omit_first which also applies to print0)
+#[derive(Debug,Clone,Default)]
+pub struct Options {
+    pub format : Format,
+    /// show the size of the files before each group (fdupes -S)
+    pub show_size : bool,
+    /// list each group on a single line (fdupes -1)
+    pub same_line : bool,
+    /// leave the survivor (and the reference files) out of each group (fdupes -f),
+    /// with print0 only the non-survivors are written, without group separators
+    pub omit_first : bool,
+    /// only print a summary instead of the groups (fdupes -m)
+    pub summarize : bool,
+    /// how much the dirs and html formats report about the directories
+    pub report : ReportOptions,
+}
+
+impl Options {
+    /// true if one of the options only found in fdupes is used
+    pub fn wants_fdupes(&self) -> bool {
+        self.show_size || self.same_line || self.omit_first || self.summarize
+    }
+}
+
+/// Write all the duplicate groups in the requested format
+/// (the summary is part of the json and html outputs and used by fdupes -m)
+pub fn write_duplicates<W>(out : &mut W, options : &Options, duplicates : &[Duplicates], summary : &Summary) -> Result<()> where W : Write {
+    match options.format {
+        Format::Text => {
+            for dup in duplicates {
+                writeln!(out, "{}", dup)?;
+            }
+        },
+        Format::Fdupes if options.summarize => fdupes::write_summary(out, summary)?,
+        Format::Fdupes => fdupes::write(out, duplicates, options)?,
+        Format::Print0 => print0::write(out, duplicates, options.omit_first)?,
+        Format::Dirs => DirReport::new(duplicates, &options.report).write(out)?,
+        Format::Html => html::write(out, duplicates, summary, &options.report)?,
+        Format::Json => json::write(out, duplicates, summary)?,
+        Format::Ndjson => ndjson::write(out, duplicates)?,
+        Format::Csv => csv::write(out, duplicates, ',')?,
+        Format::Tsv => csv::write(out, duplicates, '\t')?,
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use std::io::Write;
+use std::borrow::Cow;
+
+use crate::types::Result;
+use crate::duplicates::Duplicates;
+use crate::pathenc;
+
+const HEADER : [&str; 8] = ["group", "hash", "size", "path", "mtime", "survivor", "reference", "root"];
+
+/// Write one row per file (with a header row), fields separated by the
+/// delimiter (',' for CSV, '\t' for TSV) and quoted as per RFC 4180
+pub fn write<W>(out : &mut W, duplicates : &[Duplicates], delimiter : char) -> Result<()> where W : Write {
+    // RFC 4180 records end with CRLF, TSV files usually don't
+    let eol = if delimiter == ',' { "\r\n" } else { "\n" };
+    write_record(out, HEADER.iter().map(|s| Cow::Borrowed(*s)), delimiter, eol)?;
+    for (id, dup) in duplicates.iter().enumerate() {
+        for file in dup.files() {
+            let fields = [
+                Cow::Owned((id + 1).to_string()),
+                Cow::Borrowed(dup.hash_as_hex().as_str()),
+                Cow::Owned(dup.size().to_string()),
+                pathenc::escape(file.path()),
+                Cow::Owned(file.modified().map(|t| humantime::format_rfc3339_seconds(t).to_string()).unwrap_or_default()),
+                Cow::Borrowed(if dup.is_survivor(file) { "true" } else { "false" }),
+                Cow::Borrowed(if file.is_reference() { "true" } else { "false" }),
+                file.root().map(|r| pathenc::escape(r)).unwrap_or_default(),
+            ];
+            write_record(out, fields.into_iter(), delimiter, eol)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_record<'a,W,I>(out : &mut W, fields : I, delimiter : char, eol : &str) -> Result<()> where W : Write, I : Iterator<Item=Cow<'a, str>> {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            write!(out, "{}", delimiter)?;
+        }
+        write!(out, "{}", quote(&field, delimiter))?;
+    }
+    write!(out, "{}", eol)?;
+    Ok(())
+}
+
+/// Fields containing the delimiter, a double quote or a line break are
+/// enclosed in double quotes, with the double quotes inside doubled
+fn quote(field : &str, delimiter : char) -> Cow<'_, str> {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_the_special_fields() {
+        assert!(matches!(quote("plain", ','), Cow::Borrowed("plain")));
+        assert_eq!(quote("a,b", ','), "\"a,b\"");
+        assert_eq!(quote("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote("two\nlines", ','), "\"two\nlines\"");
+        assert_eq!(quote("two\r\nlines", ','), "\"two\r\nlines\"");
+    }
+
+    #[test]
+    fn tsv_quotes_tabs_but_not_commas() {
+        assert_eq!(quote("a,b", '\t'), "a,b");
+        assert_eq!(quote("a\tb", '\t'), "\"a\tb\"");
+        assert_eq!(quote("say \"hi\"", '\t'), "\"say \"\"hi\"\"\"");
+        let mut out = Vec::new();
+        write(&mut out, &[Duplicates::new(vec!["a,b".into(), "c\td".into()], "h".to_string(), 3)], '\t').unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "group\thash\tsize\tpath\tmtime\tsurvivor\treference\troot\n\
+            1\th\t3\ta,b\t\ttrue\tfalse\t\n\
+            1\th\t3\t\"c\td\"\t\tfalse\tfalse\t\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_paths() {
+        use std::os::unix::ffi::OsStrExt;
+        let paths = [b"/d/caf\xe9".as_slice(), b"/d/a,\xe9"].map(|p| std::ffi::OsStr::from_bytes(p).into());
+        let mut out = Vec::new();
+        write(&mut out, &[Duplicates::new(paths.to_vec(), "h".to_string(), 3)], ',').unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let rows : Vec<&str> = out.split("\r\n").collect();
+        assert_eq!(rows[1..], ["1,h,3,$'/d/caf\\xe9',,true,false,", "1,h,3,\"$'/d/a,\\xe9'\",,false,false,", ""]);
+    }
+}
@@ -0,0 +1,67 @@
+use std::io::Write;
+
+use crate::types::{Result,FileSize};
+use crate::duplicates::{Duplicates,DuplicateFile};
+use crate::stats::Summary;
+use crate::pathenc;
+use super::Options;
+
+/// Write the groups the way fdupes does: one path per line and a blank
+/// line after each group, or each group on a single line (-1) with the
+/// spaces and backslashes of the paths escaped. Paths that are not plain
+/// UTF-8 text are quoted first (see pathenc::escape_line). -S adds a size
+/// line and -f leaves the survivor out.
+pub fn write<W>(out : &mut W, duplicates : &[Duplicates], options : &Options) -> Result<()> where W : Write {
+    for dup in duplicates {
+        let files : Vec<&DuplicateFile> = if options.omit_first {
+            dup.non_survivors().collect()
+        } else {
+            dup.files().iter().collect()
+        };
+        if files.is_empty() {
+            continue;
+        }
+        if options.show_size {
+            writeln!(out, "{} byte{} each:", dup.size(), if dup.size() == 1 { "" } else { "s" })?;
+        }
+        if options.same_line {
+            let line : Vec<String> = files.iter().map(|f| escape(&pathenc::escape_line(f.path()))).collect();
+            writeln!(out, "{}", line.join(" "))?;
+        } else {
+            for file in files {
+                writeln!(out, "{}", pathenc::escape_line(file.path()))?;
+            }
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// The fdupes -m summary line
+pub fn write_summary<W>(out : &mut W, summary : &Summary) -> Result<()> where W : Write {
+    let files = summary.duplicate_files - summary.groups;
+    if files == 0 {
+        writeln!(out, "No duplicates found.")?;
+    } else {
+        writeln!(out, "{} duplicate files (in {} sets), occupying {}", files, summary.groups, human_size(summary.redundant_bytes))?;
+    }
+    Ok(())
+}
+
+fn escape(path : &str) -> String {
+    path.replace('\\', "\\\\").replace(' ', "\\ ")
+}
+
+fn human_size(bytes : FileSize) -> String {
+    const UNITS : [&str; 3] = ["kilobytes", "megabytes", "gigabytes"];
+    if bytes < 1000 {
+        return format!("{} byte{}", bytes, if bytes == 1 { "" } else { "s" });
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,197 @@
+use std::io::Write;
+use serde::Serialize;
+
+use crate::types::Result;
+use crate::duplicates::Duplicates;
+use crate::stats::Summary;
+use crate::report::{DirReport,ReportOptions};
+
+#[derive(Serialize)]
+struct Data<'a> {
+    groups : &'a [Duplicates],
+    summary : &'a Summary,
+    dirs : &'a DirReport,
+}
+
+/// Write a self-contained HTML report (no external assets): a summary
+/// header, a sortable table of the groups with expandable path lists and
+/// a treemap of the wasted space per directory. The page is rendered by
+/// the embedded script from the embedded JSON data.
+pub fn write<W>(out : &mut W, duplicates : &[Duplicates], summary : &Summary, report : &ReportOptions) -> Result<()> where W : Write {
+    let dirs = DirReport::new(duplicates, report);
+    let data = serde_json::to_string(&Data { groups : duplicates, summary, dirs : &dirs })?;
+    // '<' only appears inside JSON strings where < means the same thing,
+    // this keeps "</script>" in a path from closing the data block
+    let data = data.replace('<', "\\u003c");
+    write!(out, "{}", HEAD)?;
+    writeln!(out, "<script type=\"application/json\" id=\"data\">{}</script>", data)?;
+    write!(out, "{}", TAIL)?;
+    Ok(())
+}
+
+const HEAD : &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>fdedup report</title>
+<style>
+body { font-family: sans-serif; margin: 1em 2em; color: #222; }
+h1 { font-size: 1.4em; }
+h2 { font-size: 1.1em; margin-top: 1.5em; }
+#summary td { padding: 0.1em 1em 0.1em 0; }
+#summary td:nth-child(2) { text-align: right; font-weight: bold; }
+#groups { border-collapse: collapse; width: 100%; }
+#groups th { cursor: pointer; background: #eee; text-align: left; padding: 0.3em; user-select: none; }
+#groups th.asc::after { content: " \25B2"; }
+#groups th.desc::after { content: " \25BC"; }
+#groups td { border-top: 1px solid #ddd; padding: 0.3em; vertical-align: top; }
+#groups td.num { text-align: right; white-space: nowrap; }
+#groups code { font-size: 0.85em; }
+summary { cursor: pointer; }
+ul.paths { margin: 0.3em 0; padding-left: 1.2em; }
+li.reference { color: #06c; }
+li.survivor { font-weight: bold; }
+#treemap { position: relative; width: 100%; height: 480px; background: #f4f4f4; }
+#treemap div { position: absolute; box-sizing: border-box; border: 1px solid #fff; overflow: hidden; font-size: 11px; padding: 1px 3px; color: #000; }
+</style>
+</head>
+<body>
+<h1>fdedup report</h1>
+<noscript>This report needs JavaScript to display the data embedded in the page.</noscript>
+<table id="summary"></table>
+<h2>Wasted space per directory</h2>
+<div id="treemap"></div>
+<h2>Duplicate groups</h2>
+<table id="groups">
+<thead><tr><th data-key="id">#</th><th data-key="size">Size</th><th data-key="count">Files</th><th data-key="wasted">Wasted</th><th data-key="path">Paths</th><th data-key="hash">Hash</th></tr></thead>
+<tbody></tbody>
+</table>
+"##;
+
+const TAIL : &str = r##"<script>
+"use strict";
+const data = JSON.parse(document.getElementById("data").textContent);
+
+function human(bytes) {
+  const units = ["B", "KB", "MB", "GB", "TB", "PB"];
+  let i = 0;
+  while (bytes >= 1024 && i < units.length - 1) { bytes /= 1024; i++; }
+  return (i === 0 ? bytes : bytes.toFixed(1)) + " " + units[i];
+}
+
+function el(tag, text, cls) {
+  const e = document.createElement(tag);
+  if (text !== undefined) e.textContent = text;
+  if (cls) e.className = cls;
+  return e;
+}
+
+// summary header
+const s = data.summary;
+const summaryRows = [
+  ["Duplicate groups", s.groups],
+  ["Duplicate files", s.duplicate_files],
+  ["Redundant space", human(s.redundant_bytes) + " (" + s.redundant_bytes + " bytes)"],
+  ["Files scanned", s.files_scanned],
+  ["Files hashed", s.files_hashed],
+  ["Cache hits / misses", s.cache_hits + " / " + s.cache_misses],
+  ["Errors", s.errors],
+  ["Scan time", s.timings.scan.toFixed(2) + " s (" + human(s.hash_rate) + "/s hashed)"],
+];
+const summary = document.getElementById("summary");
+for (const [label, value] of summaryRows) {
+  const tr = el("tr");
+  tr.appendChild(el("td", label));
+  tr.appendChild(el("td", String(value)));
+  summary.appendChild(tr);
+}
+
+// groups table
+const groups = data.groups.map((g, i) => ({
+  id: i + 1, size: g.size, count: g.count, wasted: g.redundant_bytes,
+  path: g.paths[0], hash: g.hash, paths: g.paths, references: g.references || [],
+}));
+const tbody = document.querySelector("#groups tbody");
+function renderGroups() {
+  tbody.textContent = "";
+  for (const g of groups) {
+    const tr = el("tr");
+    tr.appendChild(el("td", String(g.id), "num"));
+    tr.appendChild(el("td", human(g.size), "num"));
+    tr.appendChild(el("td", String(g.count), "num"));
+    tr.appendChild(el("td", human(g.wasted), "num"));
+    const td = el("td");
+    const details = el("details");
+    details.appendChild(el("summary", g.path + (g.count > 1 ? " (+" + (g.count - 1) + ")" : "")));
+    const ul = el("ul", undefined, "paths");
+    const survivor = g.references.length > 0 ? g.references[0] : g.paths[0];
+    for (const p of g.paths) {
+      const cls = g.references.includes(p) ? "reference" : (p === survivor ? "survivor" : "");
+      ul.appendChild(el("li", p, cls));
+    }
+    details.appendChild(ul);
+    td.appendChild(details);
+    tr.appendChild(td);
+    const hash = el("td");
+    hash.appendChild(el("code", g.hash.substring(0, 16)));
+    hash.title = g.hash;
+    tr.appendChild(hash);
+    tbody.appendChild(tr);
+  }
+}
+let sortKey = "id", sortDesc = false;
+for (const th of document.querySelectorAll("#groups th")) {
+  th.addEventListener("click", () => {
+    const key = th.dataset.key;
+    sortDesc = key === sortKey ? !sortDesc : false;
+    sortKey = key;
+    groups.sort((a, b) => {
+      const c = a[key] < b[key] ? -1 : a[key] > b[key] ? 1 : a.id - b.id;
+      return sortDesc ? -c : c;
+    });
+    for (const h of document.querySelectorAll("#groups th")) h.className = "";
+    th.className = sortDesc ? "desc" : "asc";
+    renderGroups();
+  });
+}
+renderGroups();
+
+// treemap of the redundant bytes per directory (slice and dice layout)
+const nodes = new Map();
+for (const d of data.dirs.dirs) nodes.set(d.path, { path: d.path, size: d.total_bytes, children: [] });
+const tops = [];
+for (const n of nodes.values()) {
+  const cut = n.path.lastIndexOf("/");
+  const parent = cut > 0 ? nodes.get(n.path.substring(0, cut)) : undefined;
+  if (parent) parent.children.push(n); else tops.push(n);
+}
+const treemap = document.getElementById("treemap");
+function layout(list, x, y, w, h, depth) {
+  const total = list.reduce((t, n) => t + n.size, 0);
+  if (total === 0 || depth > 4) return;
+  let offset = 0;
+  for (const n of list.sort((a, b) => b.size - a.size)) {
+    const part = n.size / total;
+    const [nx, ny, nw, nh] = w >= h
+      ? [x + offset * w, y, part * w, h]
+      : [x, y + offset * h, w, part * h];
+    offset += part;
+    if (nw < 2 || nh < 2) continue;
+    const box = el("div", n.path.substring(n.path.lastIndexOf("/") + 1) + " " + human(n.size));
+    box.title = n.path + ": " + human(n.size);
+    box.style.left = nx + "%";
+    box.style.top = ny + "%";
+    box.style.width = nw + "%";
+    box.style.height = nh + "%";
+    box.style.background = "hsl(" + (200 - depth * 35) + ", 60%, " + (85 - depth * 8) + "%)";
+    treemap.appendChild(box);
+    // leave room for the label of the parent
+    const pad = Math.min(nh * 0.15, 4);
+    layout(n.children, nx, ny + pad, nw, nh - pad, depth + 1);
+  }
+}
+layout(tops, 0, 0, 100, 100, 0);
+</script>
+</body>
+</html>
+"##;
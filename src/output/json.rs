@@ -0,0 +1,20 @@
+use std::io::Write;
+use serde::Serialize;
+
+use crate::types::Result;
+use crate::duplicates::Duplicates;
+use crate::stats::Summary;
+
+#[derive(Serialize)]
+struct Document<'a> {
+    groups : &'a [Duplicates],
+    summary : &'a Summary,
+}
+
+/// Write the groups and the summary as a single JSON document.
+/// Paths that are not valid UTF-8 are escaped (see pathenc).
+pub fn write<W>(out : &mut W, duplicates : &[Duplicates], summary : &Summary) -> Result<()> where W : Write {
+    serde_json::to_writer_pretty(&mut *out, &Document { groups : duplicates, summary })?;
+    writeln!(out)?;
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use std::io::Write;
+
+use crate::types::Result;
+use crate::duplicates::Duplicates;
+use crate::event::Event;
+
+/// Write one event as a single line of JSON
+pub fn write_event<W>(out : &mut W, event : &Event) -> Result<()> where W : Write {
+    serde_json::to_writer(&mut *out, event)?;
+    writeln!(out)?;
+    Ok(())
+}
+
+/// Write one "group" record per line
+pub fn write<W>(out : &mut W, duplicates : &[Duplicates]) -> Result<()> where W : Write {
+    for dup in duplicates {
+        write_event(out, &Event::Group(dup))?;
+        // a consumer reading the stream gets each group as soon as it is written
+        out.flush()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use std::io::Write;
+
+use crate::types::Result;
+use crate::duplicates::Duplicates;
+
+/// Write the raw paths (exactly as the OS gives them) each followed by a
+/// NUL byte, with an extra NUL after each group. With only_removable, the
+/// non-survivor paths are written without group separators, ready for
+/// xargs -0 rm.
+pub fn write<W>(out : &mut W, duplicates : &[Duplicates], only_removable : bool) -> Result<()> where W : Write {
+    for dup in duplicates {
+        if only_removable {
+            for file in dup.non_survivors() {
+                out.write_all(file.path().as_os_str().as_encoded_bytes())?;
+                out.write_all(b"\0")?;
+            }
+        } else {
+            for path in dup.paths() {
+                out.write_all(path.as_os_str().as_encoded_bytes())?;
+                out.write_all(b"\0")?;
+            }
+            out.write_all(b"\0")?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::path::{Path,PathBuf};
+
+use crate::types::Result;
+
+/// Prefix/suffix marking an escaped path (shell-style ANSI-C quoting)
+const ESCAPED_START : &str = "$'";
+const ESCAPED_END : &str = "'";
+
+/// Lossless string form of a path for structured outputs (JSON...).
+/// Valid UTF-8 paths are returned as is. Other paths are written with
+/// shell-style quoting ($'...' with \xNN for the bytes that are not UTF-8),
+/// as are the paths that would otherwise look like an escaped one.
+pub fn escape(path : &Path) -> Cow<'_, str> {
+    match path.to_str() {
+        Some(s) if !s.starts_with(ESCAPED_START) => Cow::Borrowed(s),
+        _ => Cow::Owned(quote(path.as_os_str().as_encoded_bytes())),
+    }
+}
+
+/// Lossless string form of a path for line-based outputs (text, fdupes,
+/// dirs...): same as escape, but the paths containing control characters
+/// (a newline would split the path over two lines) are quoted as well.
+pub fn escape_line(path : &Path) -> Cow<'_, str> {
+    match path.to_str() {
+        Some(s) if !s.starts_with(ESCAPED_START) && !s.contains(|c : char| c.is_ascii_control()) => Cow::Borrowed(s),
+        _ => Cow::Owned(quote(path.as_os_str().as_encoded_bytes())),
+    }
+}
+
+/// The path written by escape or escape_line, with the exact same bytes
+pub fn unescape(s : &str) -> Result<PathBuf> {
+    let Some(quoted) = s.strip_prefix(ESCAPED_START).and_then(|s| s.strip_suffix(ESCAPED_END)) else {
+        return Ok(PathBuf::from(s));
+    };
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => bytes.push(b'\\'),
+            Some('\'') => bytes.push(b'\''),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('x') => {
+                let hex : String = chars.by_ref().take(2).collect();
+                let b = u8::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == 2)
+                    .ok_or_else(|| anyhow::format_err!("invalid \\x escape in path: {}",s))?;
+                bytes.push(b);
+            },
+            _ => return Err(anyhow::format_err!("invalid escape sequence in path: {}",s)),
+        }
+    }
+    from_bytes(bytes).ok_or_else(|| anyhow::format_err!("path is not valid on this platform: {}",s))
+}
+
+/// Raw bytes of a path (for binary formats), from_bytes gives it back
+#[cfg(unix)]
+pub(crate) fn to_bytes(path : &Path) -> &[u8] {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn to_bytes(path : &Path) -> &[u8] {
+    path.as_os_str().as_encoded_bytes()
+}
+
+#[cfg(unix)]
+pub(crate) fn from_bytes(bytes : Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn from_bytes(bytes : Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+fn quote(bytes : &[u8]) -> String {
+    let mut result = String::from(ESCAPED_START);
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => result.push_str("\\\\"),
+                '\'' => result.push_str("\\'"),
+                '\n' => result.push_str("\\n"),
+                '\t' => result.push_str("\\t"),
+                '\r' => result.push_str("\\r"),
+                c if c.is_control() && c.is_ascii() => result.push_str(&format!("\\x{:02x}",c as u8)),
+                c => result.push(c),
+            }
+        }
+        for b in chunk.invalid() {
+            result.push_str(&format!("\\x{:02x}",b));
+        }
+    }
+    result.push_str(ESCAPED_END);
+    result
+}
+
+/// serde helper (serialize_with) for path fields
+pub fn serialize<P,S>(path : &P, serializer : S) -> std::result::Result<S::Ok, S::Error> where P : AsRef<Path>, S : serde::Serializer {
+    serializer.serialize_str(&escape(path.as_ref()))
+}
+
+/// serde helper (serialize_with) for lists of paths
+pub fn serialize_all<P,S>(paths : &[P], serializer : S) -> std::result::Result<S::Ok, S::Error> where P : AsRef<Path>, S : serde::Serializer {
+    serializer.collect_seq(paths.iter().map(|p| escape(p.as_ref())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(path : &Path) {
+        assert_eq!(unescape(&escape(path)).unwrap(), path);
+        assert_eq!(unescape(&escape_line(path)).unwrap(), path);
+        assert_eq!(from_bytes(to_bytes(path).to_vec()).unwrap(), path);
+    }
+
+    #[test]
+    fn plain_paths_are_kept() {
+        for path in ["/usr/share/doc", "relative/file.txt", "caf\u{e9}/\u{1f600}", "with space/and'quote", "back\\slash"] {
+            assert_eq!(escape(Path::new(path)), path);
+            assert_eq!(escape_line(Path::new(path)), path);
+            round_trip(Path::new(path));
+        }
+    }
+
+    #[test]
+    fn control_characters() {
+        let path = Path::new("/tmp/two\nlines\tand\r\x01");
+        assert_eq!(escape(path), "/tmp/two\nlines\tand\r\x01");
+        assert_eq!(escape_line(path), "$'/tmp/two\\nlines\\tand\\r\\x01'");
+        round_trip(path);
+    }
+
+    #[test]
+    fn paths_that_look_escaped() {
+        let path = Path::new("$'not escaped'");
+        assert_eq!(escape(path), "$'$\\'not escaped\\''");
+        round_trip(path);
+        round_trip(Path::new("$'"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"/data/caf\xe9/\xff\xfe\n\\'"));
+        assert_eq!(escape(path), "$'/data/caf\\xe9/\\xff\\xfe\\n\\\\\\''");
+        round_trip(path);
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert!(unescape("$'\\q'").is_err());
+        assert!(unescape("$'\\x4'").is_err());
+        assert!(unescape("$'\\xzz'").is_err());
+        assert!(unescape("$'trailing\\'").is_err());
+        // not quoted at both ends: taken as is
+        assert_eq!(unescape("$'unterminated").unwrap(), Path::new("$'unterminated"));
+    }
+}
@@ -0,0 +1,205 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap,HashMap,HashSet};
+use std::io::Write;
+use std::path::Path;
+use serde::Serialize;
+
+use crate::types::{PathData,FileSize,Result};
+use crate::duplicates::Duplicates;
+use crate::pathenc;
+
+/// How much of the directory pairs a DirReport computes
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub struct ReportOptions {
+    /// number of directory pairs kept (default 20)
+    pub top_pairs : usize,
+    /// the pairs of a group are only counted between its first directories
+    /// (in path order, default 100): a group found in n directories gives
+    /// n*(n-1)/2 pairs
+    pub max_group_dirs : usize,
+}
+
+impl Default for ReportOptions {
+    fn default() -> Self {
+        Self { top_pairs : 20, max_group_dirs : 100 }
+    }
+}
+
+/// Where the redundant bytes are: per directory (rolled up the tree like du)
+/// and between the directories sharing the most duplicate content
+#[derive(Debug,Clone,Default,Serialize)]
+pub struct DirReport {
+    /// sorted by path so that each directory comes before its content
+    pub dirs : Vec<DirUsage>,
+    /// sorted by decreasing shared bytes
+    pub pairs : Vec<DirPair>,
+}
+
+#[derive(Debug,Clone,Serialize)]
+pub struct DirUsage {
+    #[serde(serialize_with = "crate::pathenc::serialize")]
+    pub path : PathData,
+    /// redundant bytes of the files directly in the directory
+    pub own_bytes : FileSize,
+    /// redundant bytes of the directory and all its subdirectories
+    pub total_bytes : FileSize,
+    /// number of redundant files in the directory and its subdirectories
+    pub files : usize,
+}
+
+#[derive(Debug,Clone,Serialize)]
+pub struct DirPair {
+    #[serde(serialize_with = "crate::pathenc::serialize")]
+    pub first : PathData,
+    #[serde(serialize_with = "crate::pathenc::serialize")]
+    pub second : PathData,
+    /// total size of the groups having a copy in both directories
+    pub shared_bytes : FileSize,
+}
+
+impl DirReport {
+    pub fn new(duplicates : &[Duplicates], options : &ReportOptions) -> Self {
+        let mut dirs : HashMap<PathData, DirUsage> = HashMap::new();
+        let mut pairs : HashMap<(PathData, PathData), FileSize> = HashMap::new();
+        for dup in duplicates {
+            // the redundant copies are the ones that would go away, hard links to
+            // data already counted in the group don't waste anything: the
+            // directories get dup.redundant_bytes() in all
+            let mut seen_ids : HashSet<_> = dup.survivor().file_id().into_iter().collect();
+            let mut redundant = dup.distinct_count().saturating_sub(1);
+            for file in dup.non_survivors() {
+                if let Some(id) = file.file_id() {
+                    if !seen_ids.insert(id) {
+                        continue;
+                    }
+                }
+                if redundant == 0 {
+                    break;
+                }
+                redundant -= 1;
+                let Some(parent) = file.path().parent() else { continue };
+                for (depth, dir) in parent.ancestors().filter(|d| !d.as_os_str().is_empty()).enumerate() {
+                    let usage = dirs.entry(dir.to_path_buf()).or_insert_with(|| DirUsage {
+                        path : dir.to_path_buf(), own_bytes : 0, total_bytes : 0, files : 0,
+                    });
+                    if depth == 0 {
+                        usage.own_bytes += dup.size();
+                    }
+                    usage.total_bytes += dup.size();
+                    usage.files += 1;
+                }
+            }
+            let mut group_pairs = HashSet::new();
+            let mut parents : Vec<&Path> = dup.paths().filter_map(|p| p.parent()).collect();
+            parents.sort_unstable();
+            parents.dedup();
+            parents.truncate(options.max_group_dirs);
+            for (i, a) in parents.iter().enumerate() {
+                for b in &parents[i+1..] {
+                    if let Some(pair) = diverging(a, b) {
+                        group_pairs.insert(pair);
+                    }
+                }
+            }
+            for pair in group_pairs {
+                *pairs.entry(pair).or_default() += dup.size();
+            }
+        }
+        let mut dirs : Vec<DirUsage> = dirs.into_values().collect();
+        dirs.sort_by(|a, b| a.path.cmp(&b.path));
+        // the smallest of the kept pairs (ties: the last in path order) is on top
+        let mut top = BinaryHeap::with_capacity(options.top_pairs.min(pairs.len()) + 1);
+        for (dirs, shared_bytes) in pairs {
+            top.push(Reverse((shared_bytes, Reverse(dirs))));
+            if top.len() > options.top_pairs {
+                top.pop();
+            }
+        }
+        let pairs = top.into_sorted_vec().into_iter()
+            .map(|Reverse((shared_bytes, Reverse((first, second))))| DirPair { first, second, shared_bytes })
+            .collect();
+        Self { dirs, pairs }
+    }
+    /// du-like listing (redundant bytes and path) followed by the pairs
+    pub fn write<W>(&self, out : &mut W) -> Result<()> where W : Write {
+        writeln!(out, "# redundant bytes per directory (including subdirectories)")?;
+        for dir in &self.dirs {
+            writeln!(out, "{}\t{}", dir.total_bytes, pathenc::escape_line(&dir.path))?;
+        }
+        if !self.pairs.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "# directories sharing the most duplicate content")?;
+            for pair in &self.pairs {
+                writeln!(out, "{}/ and {}/ share {} bytes", pathenc::escape_line(&pair.first), pathenc::escape_line(&pair.second), pair.shared_bytes)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The two directories where the paths part ways, right under their
+/// common ancestor (so that backup-2021/a/b and backup-2022/a/b give
+/// backup-2021 and backup-2022), in a stable order. None for the same directory.
+fn diverging(a : &Path, b : &Path) -> Option<(PathData, PathData)> {
+    let mut ca = a.components();
+    let mut cb = b.components();
+    let mut common = PathData::new();
+    loop {
+        match (ca.next(), cb.next()) {
+            (Some(x), Some(y)) if x == y => common.push(x),
+            (None, None) => return None,
+            (x, y) => {
+                // one of the directories can be the common ancestor itself
+                let first = x.map_or(common.clone(), |x| common.join(x));
+                let second = y.map_or(common.clone(), |y| common.join(y));
+                return Some(if first <= second { (first, second) } else { (second, first) });
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(paths : &[&str], size : FileSize) -> Duplicates {
+        Duplicates::new(paths.iter().map(PathData::from).collect(), String::new(), size)
+    }
+
+    fn pairs(report : &DirReport) -> Vec<(&str, &str, FileSize)> {
+        report.pairs.iter().map(|p| (p.first.to_str().unwrap(), p.second.to_str().unwrap(), p.shared_bytes)).collect()
+    }
+
+    #[test]
+    fn keeps_the_largest_pairs_in_order() {
+        let duplicates : Vec<_> = [10, 30, 20, 30, 5].iter().enumerate()
+            .map(|(i, &size)| group(&[&format!("p{}a/f", i), &format!("p{}b/f", i)], size))
+            .collect();
+        let report = DirReport::new(&duplicates, &ReportOptions { top_pairs : 3, ..Default::default() });
+        // ties in path order
+        assert_eq!(pairs(&report), [("p1a", "p1b", 30), ("p3a", "p3b", 30), ("p2a", "p2b", 20)]);
+        let report = DirReport::new(&duplicates, &ReportOptions { top_pairs : 0, ..Default::default() });
+        assert!(report.pairs.is_empty());
+    }
+
+    #[test]
+    fn caps_the_directories_of_a_group() {
+        let paths : Vec<String> = (0..150).rev().map(|i| format!("dir{:03}/f", i)).collect();
+        let paths : Vec<&str> = paths.iter().map(String::as_str).collect();
+        let report = DirReport::new(&[group(&paths, 1)], &ReportOptions { top_pairs : usize::MAX, ..Default::default() });
+        // the first 100 directories in path order, whatever the order of the group
+        assert_eq!(report.pairs.len(), 100 * 99 / 2);
+        assert!(report.pairs.iter().all(|p| p.first.as_path() < Path::new("dir100") && p.second.as_path() < Path::new("dir100")));
+        // all of them still count for the redundant bytes
+        assert_eq!(report.dirs.len(), 149);
+    }
+
+    #[test]
+    fn rolls_up_nested_directories() {
+        let duplicates = [group(&["a/x/f", "a/y/f", "a/y/z/f"], 10), group(&["a/x/g", "b/g"], 3)];
+        let report = DirReport::new(&duplicates, &ReportOptions::default());
+        let dirs : Vec<_> = report.dirs.iter().map(|d| (d.path.to_str().unwrap(), d.own_bytes, d.total_bytes, d.files)).collect();
+        assert_eq!(dirs, [("a", 0, 20, 2), ("a/y", 10, 20, 2), ("a/y/z", 10, 10, 1), ("b", 3, 3, 1)]);
+        assert_eq!(pairs(&report), [("a/x", "a/y", 10), ("a/y", "a/y/z", 10), ("a", "b", 3)]);
+    }
+}
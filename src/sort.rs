@@ -0,0 +1,191 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::types::Result;
+use crate::duplicates::{Duplicates,DuplicateFile};
+
+/// How the groups are ordered
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum GroupKey {
+    /// size of the files
+    #[default]
+    Size,
+    /// redundant bytes of the group
+    Wasted,
+    /// number of files in the group
+    Count,
+    /// first path of the group (once the paths are sorted)
+    Path,
+    /// oldest modification time of the group
+    Mtime,
+}
+
+/// How the paths are ordered inside a group
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub enum PathKey {
+    #[default]
+    Name,
+    Mtime,
+    /// number of components of the path (shallowest first)
+    Depth,
+}
+
+/// A sort key and a direction, parsed from "key" or "key:asc" or "key:desc"
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Default)]
+pub struct SortOrder<K> {
+    pub key : K,
+    pub descending : bool,
+}
+
+impl FromStr for GroupKey {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "size" => Ok(GroupKey::Size),
+            "wasted" => Ok(GroupKey::Wasted),
+            "count" => Ok(GroupKey::Count),
+            "path" => Ok(GroupKey::Path),
+            "mtime" => Ok(GroupKey::Mtime),
+            _ => Err(anyhow::format_err!("unknown group sort key: {} (expected size, wasted, count, path or mtime)",s)),
+        }
+    }
+}
+
+impl FromStr for PathKey {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(PathKey::Name),
+            "mtime" => Ok(PathKey::Mtime),
+            "depth" => Ok(PathKey::Depth),
+            _ => Err(anyhow::format_err!("unknown path sort key: {} (expected name, mtime or depth)",s)),
+        }
+    }
+}
+
+impl<K> FromStr for SortOrder<K> where K : FromStr<Err = anyhow::Error> {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.to_ascii_lowercase();
+        let (key, descending) = match s.split_once(':') {
+            None => (s.as_str(), false),
+            Some((key, "asc")) => (key, false),
+            Some((key, "desc")) => (key, true),
+            Some((_, order)) => return Err(anyhow::format_err!("unknown sort order: {} (expected asc or desc)",order)),
+        };
+        Ok(Self { key : key.parse()?, descending })
+    }
+}
+
+fn directed(ordering : Ordering, descending : bool) -> Ordering {
+    if descending { ordering.reverse() } else { ordering }
+}
+
+fn compare_files(a : &DuplicateFile, b : &DuplicateFile, key : PathKey) -> Ordering {
+    match key {
+        PathKey::Name => Ordering::Equal,
+        PathKey::Mtime => a.modified().cmp(&b.modified()),
+        PathKey::Depth => a.path().components().count().cmp(&b.path().components().count()),
+    }
+}
+
+/// Sort the paths of a group. Ties are broken by name so the order never
+/// depends on the order in which the files were hashed. The survivor (see
+/// Duplicates::survivor) stays first when the group has a reference file.
+pub fn sort_paths(duplicates : &mut Duplicates, order : SortOrder<PathKey>) {
+    let files = duplicates.files_mut();
+    files.sort_by(|a, b| {
+        directed(compare_files(a, b, order.key).then_with(|| a.path().cmp(b.path())), order.descending)
+    });
+    if let Some(survivor) = files.iter().position(|f| f.is_reference()) {
+        files[..=survivor].rotate_right(1);
+    }
+}
+
+/// Sort the groups. Ties are broken by size, then by hash, so that two
+/// runs on the same files always give the same order.
+pub fn sort_groups(duplicates : &mut [Duplicates], order : SortOrder<GroupKey>) {
+    duplicates.sort_by(|a, b| {
+        let ordering = match order.key {
+            GroupKey::Size => Ordering::Equal,
+            GroupKey::Wasted => a.redundant_bytes().cmp(&b.redundant_bytes()),
+            GroupKey::Count => a.count().cmp(&b.count()),
+            GroupKey::Path => a.paths().next().cmp(&b.paths().next()),
+            GroupKey::Mtime => oldest(a).cmp(&oldest(b)),
+        };
+        directed(ordering.then_with(|| a.size().cmp(&b.size())).then_with(|| a.hash_as_hex().cmp(b.hash_as_hex())), order.descending)
+    });
+}
+
+fn oldest(duplicates : &Duplicates) -> Option<std::time::SystemTime> {
+    duplicates.files().iter().filter_map(|f| f.modified()).min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration,UNIX_EPOCH};
+    use crate::hashedfile::{HashedFile,FileStat};
+
+    fn group(paths : &[&str], hash : &str, size : u64) -> Duplicates {
+        Duplicates::new(paths.iter().map(|p| p.into()).collect(), hash.to_string(), size)
+    }
+
+    fn paths(dup : &Duplicates) -> Vec<&str> {
+        dup.paths().map(|p| p.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_key_and_order() {
+        assert_eq!("wasted".parse::<SortOrder<GroupKey>>().unwrap(), SortOrder { key : GroupKey::Wasted, descending : false });
+        assert_eq!("Count:ASC".parse::<SortOrder<GroupKey>>().unwrap(), SortOrder { key : GroupKey::Count, descending : false });
+        assert_eq!("depth:desc".parse::<SortOrder<PathKey>>().unwrap(), SortOrder { key : PathKey::Depth, descending : true });
+        let error = "bytes:desc".parse::<SortOrder<GroupKey>>().unwrap_err();
+        assert_eq!(error.to_string(), "unknown group sort key: bytes (expected size, wasted, count, path or mtime)");
+        let error = "size".parse::<SortOrder<PathKey>>().unwrap_err();
+        assert_eq!(error.to_string(), "unknown path sort key: size (expected name, mtime or depth)");
+        let error = "size:up".parse::<SortOrder<GroupKey>>().unwrap_err();
+        assert_eq!(error.to_string(), "unknown sort order: up (expected asc or desc)");
+    }
+
+    #[test]
+    fn ties_are_broken_by_size_then_hash() {
+        let mut groups = [group(&["/c", "/d"], "bb", 2), group(&["/e", "/f"], "cc", 1), group(&["/a", "/b"], "aa", 2)];
+        sort_groups(&mut groups, "count".parse().unwrap());
+        assert_eq!(groups.iter().map(|g| g.hash_as_hex().as_str()).collect::<Vec<_>>(), ["cc", "aa", "bb"]);
+        sort_groups(&mut groups, "count:desc".parse().unwrap());
+        assert_eq!(groups.iter().map(|g| g.hash_as_hex().as_str()).collect::<Vec<_>>(), ["bb", "aa", "cc"]);
+    }
+
+    #[test]
+    fn paths_by_key_then_name() {
+        let mut dup = group(&["/b/deep/x", "/a/y", "/c/z"], "aa", 1);
+        sort_paths(&mut dup, SortOrder::default());
+        assert_eq!(paths(&dup), ["/a/y", "/b/deep/x", "/c/z"]);
+        sort_paths(&mut dup, "depth:desc".parse().unwrap());
+        assert_eq!(paths(&dup), ["/b/deep/x", "/c/z", "/a/y"]);
+        // the deepest is the newest
+        let files = dup.files().iter().enumerate().map(|(i, file)| {
+            let stat = FileStat { modified : Some(UNIX_EPOCH + Duration::from_secs(10 - i as u64)), ..Default::default() };
+            DuplicateFile::from(&HashedFile::from_parts(file.path().clone(), vec![], stat, None))
+        }).collect();
+        let mut dup = Duplicates::from_files(files, "aa".to_string(), 1);
+        sort_paths(&mut dup, "mtime".parse().unwrap());
+        assert_eq!(paths(&dup), ["/a/y", "/c/z", "/b/deep/x"]);
+    }
+
+    #[test]
+    fn the_survivor_stays_first() {
+        let mut dup = group(&["/scan/a", "/ref/b", "/scan/c", "/ref/d"], "aa", 1);
+        for file in dup.files_mut() {
+            let reference = file.path().starts_with("/ref");
+            file.set_reference(reference);
+        }
+        sort_paths(&mut dup, "name:desc".parse().unwrap());
+        assert_eq!(paths(&dup), ["/ref/d", "/scan/c", "/scan/a", "/ref/b"]);
+        assert!(dup.is_survivor(&dup.files()[0]));
+        sort_paths(&mut dup, SortOrder::default());
+        assert_eq!(paths(&dup), ["/ref/b", "/ref/d", "/scan/a", "/scan/c"]);
+        assert!(dup.is_survivor(&dup.files()[0]));
+    }
+}
@@ -0,0 +1,175 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use serde::{Serialize,Serializer};
+
+use crate::duplicates::Duplicates;
+use crate::types::FileSize;
+use crate::cache::{Invalidation,PruneStats};
+
+/// Number of groups listed in Summary::largest_groups
+pub const LARGEST_GROUPS : usize = 5;
+
+/// Figures about a run: what was scanned, what was found and how long it took.
+/// Summary::new only fills the figures computed from the groups,
+/// Deduplicator::summary fills everything.
+#[derive(Debug,Clone,Default,Serialize)]
+pub struct Summary {
+    pub files_scanned : u64,
+    pub files_hashed : u64,
+    pub cache_hits : u64,
+    /// files that were not in the cache (or had changed) and had to be hashed
+    pub cache_misses : u64,
+    /// cache entries found for the path but not reused, by reason
+    pub cache_invalidated : Invalidations,
+    /// cache entries dropped when writing the cache (see cache::PrunePolicy)
+    pub cache_pruned : PruneStats,
+    pub errors : u64,
+    pub skipped_recent : u64,
+    pub skipped_out_of_range : u64,
+    pub bytes_hashed : u64,
+    pub groups : usize,
+    pub duplicate_files : usize,
+    /// bytes that could be reclaimed: size x (count - 1) for each group,
+    /// not counting hard links
+    pub redundant_bytes : FileSize,
+    /// the groups wasting the most space
+    pub largest_groups : Vec<GroupSummary>,
+    pub timings : Timings,
+    /// bytes hashed per second during the scan
+    pub hash_rate : f64,
+}
+
+/// Number of cache entries not reused, for each reason (see cache::Invalidation)
+#[derive(Debug,Clone,Copy,Default,Serialize)]
+pub struct Invalidations {
+    pub mtime : u64,
+    pub size : u64,
+    pub file_id : u64,
+    pub ctime : u64,
+    pub unverified : u64,
+    pub content : u64,
+}
+
+impl Invalidations {
+    pub fn total(&self) -> u64 {
+        self.mtime + self.size + self.file_id + self.ctime + self.unverified + self.content
+    }
+    pub(crate) fn from_counters(counters : &[AtomicU64; Invalidation::COUNT]) -> Self {
+        let count = |reason : Invalidation| get(&counters[reason as usize]);
+        Self {
+            mtime : count(Invalidation::Mtime),
+            size : count(Invalidation::Size),
+            file_id : count(Invalidation::FileId),
+            ctime : count(Invalidation::Ctime),
+            unverified : count(Invalidation::Unverified),
+            content : count(Invalidation::Content),
+        }
+    }
+}
+
+#[derive(Debug,Clone,Serialize)]
+pub struct GroupSummary {
+    pub hash : String,
+    pub size : FileSize,
+    pub count : usize,
+    pub redundant_bytes : FileSize,
+}
+
+/// Time spent in each phase of a run
+#[derive(Debug,Clone,Copy,Default,Serialize)]
+pub struct Timings {
+    #[serde(serialize_with = "as_secs")]
+    pub cache_read : Duration,
+    /// walking the folders and hashing the files
+    #[serde(serialize_with = "as_secs")]
+    pub scan : Duration,
+    #[serde(serialize_with = "as_secs")]
+    pub grouping : Duration,
+    #[serde(serialize_with = "as_secs")]
+    pub cache_write : Duration,
+}
+
+fn as_secs<S>(duration : &Duration, serializer : S) -> std::result::Result<S::Ok, S::Error> where S : Serializer {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl Summary {
+    pub fn new(duplicates : &[Duplicates]) -> Self {
+        let mut largest : Vec<&Duplicates> = duplicates.iter().collect();
+        largest.sort_by_key(|d| std::cmp::Reverse(d.redundant_bytes()));
+        Self {
+            groups : duplicates.len(),
+            duplicate_files : duplicates.iter().map(|d| d.count()).sum(),
+            redundant_bytes : duplicates.iter().map(|d| d.redundant_bytes()).sum(),
+            largest_groups : largest.into_iter().take(LARGEST_GROUPS).map(|d| GroupSummary {
+                hash : d.hash_as_hex().clone(),
+                size : d.size(),
+                count : d.count(),
+                redundant_bytes : d.redundant_bytes(),
+            }).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "# files scanned: {} (hashed: {}, cache hits: {}, cache misses: {}, errors: {})",
+            self.files_scanned, self.files_hashed, self.cache_hits, self.cache_misses, self.errors)?;
+        let inv = &self.cache_invalidated;
+        if inv.total() > 0 {
+            writeln!(f, "# cache entries invalidated: {} (mtime: {}, size: {}, inode: {}, ctime: {}, unverified: {}, content: {})",
+                inv.total(), inv.mtime, inv.size, inv.file_id, inv.ctime, inv.unverified, inv.content)?;
+        }
+        if self.cache_pruned.pruned() > 0 {
+            writeln!(f, "# {}", self.cache_pruned)?;
+        }
+        if self.skipped_recent > 0 || self.skipped_out_of_range > 0 {
+            writeln!(f, "# files skipped: {} modified too recently, {} out of the time range",
+                self.skipped_recent, self.skipped_out_of_range)?;
+        }
+        writeln!(f, "# duplicates: {} groups, {} files, {} redundant bytes",
+            self.groups, self.duplicate_files, self.redundant_bytes)?;
+        if !self.largest_groups.is_empty() {
+            writeln!(f, "# largest groups:")?;
+            for g in &self.largest_groups {
+                writeln!(f, "#   {} redundant bytes: {} x {} bytes {}", g.redundant_bytes, g.count, g.size, g.hash)?;
+            }
+        }
+        writeln!(f, "# elapsed: cache read {:.3}s, scan {:.3}s, grouping {:.3}s, cache write {:.3}s",
+            self.timings.cache_read.as_secs_f64(), self.timings.scan.as_secs_f64(),
+            self.timings.grouping.as_secs_f64(), self.timings.cache_write.as_secs_f64())?;
+        writeln!(f, "# hashed: {} bytes ({:.0} bytes/s)", self.bytes_hashed, self.hash_rate)
+    }
+}
+
+/// Counters updated during a run (shared between threads)
+#[derive(Debug,Default)]
+pub(crate) struct Counters {
+    pub(crate) files_seen : AtomicU64,
+    pub(crate) files_hashed : AtomicU64,
+    pub(crate) bytes_hashed : AtomicU64,
+    pub(crate) cache_hits : AtomicU64,
+    pub(crate) errors : AtomicU64,
+    pub(crate) skipped_recent : AtomicU64,
+    pub(crate) skipped_out_of_range : AtomicU64,
+    /// indexed by cache::Invalidation
+    pub(crate) invalidated : [AtomicU64; Invalidation::COUNT],
+}
+
+impl Counters {
+    pub(crate) fn reset(&self) {
+        for counter in [&self.files_seen, &self.files_hashed, &self.bytes_hashed, &self.cache_hits,
+                        &self.errors, &self.skipped_recent, &self.skipped_out_of_range].into_iter().chain(&self.invalidated) {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+pub(crate) fn add(counter : &AtomicU64, value : u64) {
+    counter.fetch_add(value, Ordering::Relaxed);
+}
+
+pub(crate) fn get(counter : &AtomicU64) -> u64 {
+    counter.load(Ordering::Relaxed)
+}